@@ -1,4 +1,9 @@
-use clap::Clap;
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+use clap::{AppSettings, Clap};
+
+use pathfix::config::{ConfigFileFormat, PathOs, SystemProfile};
 
 #[derive(Clap)]
 #[clap(version = crate_version!(), author = crate_authors!(), after_help = include_str!("after_help.txt"))]
@@ -9,15 +14,70 @@ pub struct Opts {
     /// Outputs line by line instead of the default colon seperated list
     #[clap(short, long)]
     pub lines: bool,
+    /// Outputs shell commands which export PATH and the configured environment variables
+    #[clap(short = 'x', long, conflicts_with = "lines")]
+    pub export: bool,
+    /// Outputs PATH and the exported environment variables in the environment.d format of systemd
+    #[clap(long, conflicts_with_all = &["lines", "export"])]
+    pub systemd: bool,
+    /// Outputs the PATH for administrators as secure_path for a sudoers drop-in
+    ///
+    /// $PATH, ~/.pathfix.toml and the environment of the invoking user are not read.
+    #[clap(long, conflicts_with_all = &["lines", "export", "systemd"])]
+    pub sudoers: bool,
+    /// Outputs the PATH for administrators as line for /etc/environment or pam_env
+    ///
+    /// $PATH, ~/.pathfix.toml and the environment of the invoking user are not read.
+    #[clap(long, conflicts_with_all = &["lines", "export", "systemd", "sudoers"])]
+    pub etc_environment: bool,
     /// Includes path's from $PATH in environment
     #[clap(short='e', long)]
     pub from_env: bool,
     /// Searches included path's using inbuild configuration
     #[clap(short, long)]
     pub included: bool,
-    /// Uses the specific configuration file
+    /// Reads /etc/paths and /etc/paths.d like path_helper on macOS
+    #[clap(long)]
+    pub path_helper: bool,
+    /// Uses the specific configuration file, the format is chosen by its extension
     #[clap(short, long)]
     pub config: Option<String>,
+    /// Fails if a required path is missing instead of printing a warning
+    #[clap(long)]
+    pub strict: bool,
+    /// Checks paths below this directory, e.g. the root file system of a container image
+    ///
+    /// The system configuration, the operating system and the groups are read from the root,
+    /// ~/.pathfix.toml is ignored. Paths are printed without the root.
+    #[clap(long, parse(from_os_str))]
+    pub root: Option<PathBuf>,
+    /// Evaluates paths for another operating system, e.g. macos
+    #[clap(long)]
+    pub target_os: Option<PathOs>,
+    /// Evaluates the configuration of another user, e.g. when running as root
+    ///
+    /// The home directory and the groups of the user are read from the passwd database,
+    /// including the user's ~/.pathfix.toml. $PATH of the invoking user is not read,
+    /// unless --from-env is given.
+    #[clap(long, conflicts_with = "as-user")]
+    pub user: Option<String>,
+    /// Evaluates the administrative rights of another user
+    ///
+    /// ~/.pathfix.toml of the invoking user is not read, its $PATH only with --from-env.
+    #[clap(long)]
+    pub as_user: Option<String>,
+    /// Includes paths for administrators
+    #[clap(long, conflicts_with = "no-admin")]
+    pub admin: bool,
+    /// Excludes paths for administrators
+    #[clap(long)]
+    pub no_admin: bool,
+    /// Outputs all paths without checking if they exist
+    #[clap(long)]
+    pub no_fs_check: bool,
+    /// Does neither use nor update the result cache
+    #[clap(long)]
+    pub no_cache: bool,
     /// Use recommended flags -dei. If -e, i or -c are not set, default is assumed.
     ///
     /// Use this flag to use the recommended settings for pathfix.
@@ -26,15 +86,137 @@ pub struct Opts {
     /// 'export PATH=$(/usr/bin/pathfix)' to your .bashrc/.zshrc/... file is enough.
     #[clap(short='D', long)]
     pub defaults: bool,
+    #[clap(subcommand)]
+    pub subcommand: Option<SubCommand>,
+}
+
+#[derive(Clap)]
+pub enum SubCommand {
+    /// Manages the cache in $XDG_CACHE_HOME/pathfix
+    Cache(CacheOpts),
+    /// Runs a command with the generated PATH and the environment variables from the configuration
+    Exec(ExecOpts),
+    /// Converts a configuration file into another format, e.g. from txt to toml
+    Convert(ConvertOpts),
+    /// Shows the configuration which is used to generate the PATH
+    Config(ConfigOpts),
+}
+
+#[derive(Clap)]
+pub struct ConfigOpts {
+    #[clap(subcommand)]
+    pub action: ConfigAction,
+}
+
+#[derive(Clap)]
+pub enum ConfigAction {
+    /// Prints the merged configuration as TOML, with comments naming the source of each setting
+    Dump,
+}
+
+#[derive(Clap)]
+pub struct CacheOpts {
+    #[clap(subcommand)]
+    pub action: CacheAction,
+}
+
+#[derive(Clap)]
+#[clap(setting = AppSettings::TrailingVarArg)]
+pub struct ExecOpts {
+    /// The program and its arguments
+    #[clap(parse(from_os_str), required = true)]
+    pub command: Vec<OsString>,
+}
+
+#[derive(Clap)]
+pub struct ConvertOpts {
+    /// The configuration file to read, the format is chosen by its extension
+    #[clap(parse(from_os_str))]
+    pub input: PathBuf,
+    /// The file to write, the configuration is printed if it is missing
+    #[clap(parse(from_os_str))]
+    pub output: Option<PathBuf>,
+    /// Format of the output: toml, txt, paths, json or yaml. Defaults to the extension of the output
+    #[clap(short, long, required_unless_present = "output")]
+    pub to: Option<ConfigFileFormat>,
+}
+
+#[derive(Clap)]
+pub enum CacheAction {
+    /// Removes all cached results and command outputs
+    Clear,
+}
+
+/// Format in which the generated PATH is printed
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum OutputFormat {
+    Colon,
+    Lines,
+    Export,
+    Systemd,
+    Sudoers,
+    EtcEnvironment,
+}
+
+impl OutputFormat {
+    /// Returns if the format is meant for administrators regardless of the invoking user
+    pub fn is_system_wide(self) -> bool {
+        matches!(self, OutputFormat::Sudoers | OutputFormat::EtcEnvironment)
+    }
 }
 
 impl Opts {
+    pub fn format(&self) -> OutputFormat {
+        if self.lines {
+            OutputFormat::Lines
+        } else if self.export {
+            OutputFormat::Export
+        } else if self.systemd {
+            OutputFormat::Systemd
+        } else if self.sudoers {
+            OutputFormat::Sudoers
+        } else if self.etc_environment {
+            OutputFormat::EtcEnvironment
+        } else {
+            OutputFormat::Colon
+        }
+    }
+
+    /// Returns the system for which paths are evaluated
+    pub fn profile(&self) -> SystemProfile {
+        let mut profile = SystemProfile::this_system();
+        if let Some(os) = self.target_os {
+            profile = profile.with_os(os);
+        }
+        if let Some(user) = self.user.as_ref().or(self.as_user.as_ref()) {
+            profile = profile.with_user(user);
+        }
+        if let Some(root) = &self.root {
+            profile = profile.with_root(root);
+        }
+        // system wide outputs must not depend on the invoking user
+        if self.admin || (self.format().is_system_wide() && !self.no_admin) {
+            profile = profile.with_admin(true);
+        } else if self.no_admin {
+            profile = profile.with_admin(false);
+        }
+        if self.no_fs_check {
+            profile = profile.without_fs_check();
+        }
+        profile
+    }
+
     pub fn dedup(&self) -> bool {
         self.dedup || self.defaults()
     }
 
+    #[allow(clippy::wrong_self_convention)]
     pub fn from_env(&self) -> bool {
-        self.from_env || self.defaults()
+        if self.format().is_system_wide() {
+            return false;
+        }
+        // the $PATH of the invoking user only belongs to another user if asked for
+        self.from_env || (self.defaults() && self.user.is_none() && self.as_user.is_none())
     }
 
     pub fn included(&self) -> bool {
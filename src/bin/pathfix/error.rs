@@ -3,6 +3,9 @@ use std::env::VarError;
 use std::io;
 use thiserror::Error;
 
+use pathfix::config::EnvError;
+use pathfix::ResolveError;
+
 #[derive(Error, Debug)]
 #[error(transparent)]
 pub struct Error {
@@ -12,6 +15,31 @@ pub struct Error {
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+impl Error {
+    /// Error if required paths are missing in strict mode
+    pub fn missing_required(count: usize) -> Error {
+        Repr::MissingRequired(count).into()
+    }
+
+    /// Error if a user does not exist in the passwd database
+    pub fn unknown_user<S: Into<String>>(name: S) -> Error {
+        Repr::UnknownUser(name.into()).into()
+    }
+
+    /// Returns if required paths are missing in strict mode
+    pub fn is_missing_required(&self) -> bool {
+        matches!(self.repr, Repr::MissingRequired(_))
+    }
+
+    /// Exit code which will be returned by the binary
+    pub fn exit_code(&self) -> i32 {
+        match self.repr {
+            Repr::MissingRequired(_) => 2,
+            _ => 1,
+        }
+    }
+}
+
 #[derive(Error, Debug)]
 enum Repr {
     #[error("clap: {0}")]
@@ -20,6 +48,12 @@ enum Repr {
     Var(#[from] VarError),
     #[error(transparent)]
     Io(#[from] io::Error),
+    #[error(transparent)]
+    Env(#[from] EnvError),
+    #[error("{0} required path(s) are missing")]
+    MissingRequired(usize),
+    #[error("unknown user {0}")]
+    UnknownUser(String),
 }
 
 impl From<clap::Error> for Error {
@@ -38,4 +72,21 @@ impl From<io::Error> for Error {
     fn from(err: io::Error) -> Self {
         Repr::Io(err).into()
     }
-}
\ No newline at end of file
+}
+
+impl From<EnvError> for Error {
+    fn from(err: EnvError) -> Self {
+        Repr::Env(err).into()
+    }
+}
+
+impl From<ResolveError> for Error {
+    fn from(err: ResolveError) -> Self {
+        match err {
+            ResolveError::Io(err) => Repr::Io(err).into(),
+            ResolveError::Var(err) => Repr::Var(err).into(),
+            ResolveError::Env(err) => Repr::Env(err).into(),
+            ResolveError::MissingRequired(missing) => Repr::MissingRequired(missing.len()).into(),
+        }
+    }
+}
@@ -8,126 +8,363 @@ extern crate log;
 #[cfg(feature = "logging")]
 extern crate env_logger;
 
-use log::Level::Debug;
+use std::io;
+use std::collections::HashMap;
+use std::env::VarError;
+use std::ffi::{OsStr, OsString};
+use std::io::Write;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process;
 
-use std::{io, fs};
-use std::collections::HashSet;
-
-use pathfix::config::{Config, IncludeAdministrative, Paths, PathFlags};
+use pathfix::config::{
+    clear_cache, CachedResult, CommandCache, Config, ConfigFileDescription, EnvKind, EnvValue, IncludeAdministrative,
+    ResultCache, ResultKey, SystemProfile, PATH_HELPER_DIR, PATH_HELPER_FILE,
+};
+use pathfix::{DedupMode, ResolveError, Resolver, SYSTEM_CONFIG_FILE};
 
 mod cli;
+use cli::OutputFormat;
 
 mod error;
-use error::Result;
+use error::{Error, Result};
 
-fn run() -> Result<()> {
-    let opts = cli::opts();
+const FAILSAFE_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
 
-    let mut env_config = Config::new().with_env();
+const PASSWD_FILE: &str = "/etc/passwd";
 
-    // Use paths from environment if -e is set
-    if opts.from_env() {
-        env_config.paths = Paths::from_env()?;
-        info!("Loaded contents of $PATH variable");
+const GROUP_FILE: &str = "/etc/group";
+
+/// Result of `generate`
+struct Generated {
+    /// Existing paths, in order
+    path: Vec<PathBuf>,
+    /// All paths whose existence was checked
+    candidates: Vec<PathBuf>,
+    warnings: Vec<String>,
+    config: Config,
+    /// Resolved environment variables
+    env: HashMap<String, OsString>,
+}
+
+/// Returns ~/.pathfix.toml of the evaluated user, if it is read
+fn home_config_file(opts: &cli::Opts, profile: &SystemProfile) -> Result<Option<PathBuf>> {
+    if opts.format().is_system_wide() {
+        // system wide outputs do not belong to any user
+        Ok(None)
+    } else if let Some(user) = &opts.user {
+        let home = profile.home().ok_or_else(|| Error::unknown_user(user))?;
+        Ok(Some(profile.in_root(&home).join(".pathfix.toml")))
+    } else if opts.root.is_some() || opts.as_user.is_some() {
+        // the configuration of the invoking user does not belong to another root or user
+        Ok(None)
+    } else {
+        let home = std::env::var_os("HOME").ok_or(VarError::NotPresent)?;
+        Ok(Some(PathBuf::from(home).join(".pathfix.toml")))
     }
+}
 
-    let mut config = env_config;
+/// Joins paths as raw bytes
+fn join_paths(path: &[PathBuf], separator: u8) -> Vec<u8> {
+    let mut joined = Vec::new();
+    for (i, p) in path.iter().enumerate() {
+        if i > 0 {
+            joined.push(separator);
+        }
+        joined.extend_from_slice(p.as_os_str().as_bytes());
+    }
+    joined
+}
 
-    if opts.included() {
-        let load_config = |path: &str| {
-            Config::from_file(path)
-                .map(Some)
-                .or_else(|err| match err.kind() {
-                    io::ErrorKind::NotFound => Ok(None),
-                    _ => Err(err)
-                })
-        };
-
-        if let Some(home_config) = load_config(
-            &format!("{}/.pathfix.toml", std::env::var("HOME")?)
-        )? {
-            config = home_config.merge(config);
-            info!("Loaded config from ~/.pathfix.toml");
-        } else {
-            info!("~/.pathfix.toml is missing")
-        }
-
-        if !config.base {
-            if let Some(etc_config) = load_config("/etc/pathfix.toml")? {
-                config = etc_config.merge(config);
-                info!("Loaded config from /etc/pathfix.toml");
-            } else {
-                info!("/etc/pathfix.toml is missing")
+/// Prints the generated PATH, or a cached result of a previous run
+fn run(opts: &cli::Opts) -> Result<()> {
+    if let Some(cli::SubCommand::Cache(cache_opts)) = &opts.subcommand {
+        match cache_opts.action {
+            cli::CacheAction::Clear => clear_cache()?,
+        }
+        return Ok(());
+    }
+
+    // Results are keyed on everything which is known before reading the configuration
+    let process_env: HashMap<String, OsString> = std::env::vars_os()
+        .filter_map(|(name, value)| Some((name.into_string().ok()?, value)))
+        .collect();
+    let args: Vec<String> = std::env::args_os()
+        .skip(1)
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+    // the home directories and the groups of users decide about `~user` and admin paths
+    let mut result_key = ResultKey::new(&args)
+        .with_file(SYSTEM_CONFIG_FILE)
+        .with_file(PASSWD_FILE)
+        .with_file(GROUP_FILE);
+    if let Some(home_config_file) = home_config_file(opts, &opts.profile())? {
+        result_key = result_key.with_file(home_config_file);
+    }
+    if let Some(config_file) = &opts.config {
+        result_key = result_key.with_file(config_file);
+    }
+    if opts.path_helper {
+        result_key = result_key.with_file(PATH_HELPER_FILE).with_dir(PATH_HELPER_DIR);
+    }
+    let mut result_cache = match ResultCache::default_location() {
+        // directories are checked below another root, so they can not be validated
+        Some(location) if !opts.no_cache && opts.root.is_none() => ResultCache::open(location),
+        _ => ResultCache::disabled(),
+    };
+    if let Some(result) = result_cache.get(&result_key, &process_env) {
+        debug!("Using cached result");
+        for warning in &result.warnings {
+            eprintln!("pathfix: warning: {}", warning);
+        }
+        io::stdout().write_all(result.output.as_bytes())?;
+        return Ok(());
+    }
+
+    let Generated { path, candidates, warnings, config, env } = generate(opts)?;
+
+    // print output to stdout, paths are written as raw bytes
+    let output = match opts.format() {
+        OutputFormat::Colon | OutputFormat::Lines => {
+            let lines = opts.format() == OutputFormat::Lines;
+            let mut output = join_paths(&path, if lines { b'\n' } else { b':' });
+            if !lines || !path.is_empty() {
+                output.push(b'\n');
+            }
+            output
+        }
+        OutputFormat::Export => {
+            let mut output = export_statement("PATH", &join_paths(&path, b':'));
+            for (name, value) in config.exports(&env) {
+                output.extend(export_statement(name, value.as_bytes()));
             }
+            output
         }
+        OutputFormat::Systemd => {
+            let path = join_paths(&path, b':');
+            let mut output = Vec::new();
+            for (name, value) in std::iter::once(("PATH", OsStr::from_bytes(&path))).chain(config.exports(&env)) {
+                match value.to_str() {
+                    Some(value) => output.extend(environment_d_statement(name, value).into_bytes()),
+                    None => warn!("Skipping {}, because systemd requires UTF-8 values", name),
+                }
+            }
+            output
+        }
+        OutputFormat::Sudoers => format!("Defaults secure_path=\"{}\"\n", quoted_path(&path)).into_bytes(),
+        OutputFormat::EtcEnvironment => format!("PATH=\"{}\"\n", quoted_path(&path)).into_bytes(),
+    };
+    io::stdout().write_all(&output)?;
 
-        // Use included paths if -s is set
-        if opts.included() && !config.base {
-            let included_config = Config::included();
+    // outputs of commands can change with their programs and sudoers files can include others,
+    // which are not part of the result key, cached outputs are still taken from the command cache
+    let commands = config.env.values().any(|value| matches!(value.kind(), EnvKind::Command(_)));
+    let sudoers = config.include_administrative.as_ref().is_some_and(IncludeAdministrative::reads_sudoers);
+    if let (Ok(output), false) = (String::from_utf8(output), commands || sudoers) {
+        let mut env_names = config.references();
+        for name in &["PATH", "HOME"] {
+            if !env_names.iter().any(|n| n == name) {
+                env_names.push(name.to_string());
+            }
+        }
+        let result = CachedResult { output, warnings };
+        result_cache.insert(&result_key, env_names, &process_env, &candidates, result);
+        if let Err(err) = result_cache.save() {
+            warn!("Could not save result cache: {}", err);
+        }
+    }
 
-            // Merge included config and config from path
-            config = included_config.merge(config);
-            info!("Loaded included config");
+    Ok(())
+}
+
+/// Creates a resolver for the sources and the system given on the command line
+fn resolver(opts: &cli::Opts) -> Result<Resolver> {
+    let profile = opts.profile();
+    let home_config_file = home_config_file(opts, &profile)?;
+    let mut resolver = Resolver::new(profile.clone())
+        .with_command_cache(CommandCache::default_location()
+            .map(CommandCache::open)
+            .unwrap_or_else(CommandCache::disabled));
+
+    // system wide outputs must not depend on the environment of the invoking user,
+    // so paths in its home and unset variables are dropped
+    let system_wide = opts.format().is_system_wide();
+    if system_wide {
+        if opts.from_env {
+            warn!("Ignoring $PATH, because the output is system wide");
         }
+        resolver = resolver.without_process_env();
     }
 
+    // `~` and the environment refer to the evaluated user
+    if let (Some(user), Some(home), false) = (&opts.user, profile.home(), system_wide) {
+        resolver = resolver
+            .with_env_var("HOME", EnvValue::literal(home))
+            .with_env_var("USER", EnvValue::literal(user))
+            .with_env_var("LOGNAME", EnvValue::literal(user));
+    }
+    if opts.from_env() {
+        resolver = resolver.with_path_var();
+    }
+    if opts.included() {
+        resolver = resolver.with_included();
+        match home_config_file {
+            Some(home_config_file) => resolver = resolver.with_home_config(home_config_file),
+            None => info!("Skipping ~/.pathfix.toml of the invoking user"),
+        }
+    }
+    if opts.path_helper {
+        resolver = resolver.with_path_helper();
+    }
     if let Some(config_file) = &opts.config {
-        let load_config = Config::from_file(&config_file)?;
-        config = load_config.merge(config);
-        info!("Loaded specified config file {}", config_file);
-    }
-
-    debug!("Merged config:");
-    if log_enabled!(Debug) {
-        for path in config.paths.0.iter() {
-            if let Some(path_source) = path.source() {
-                debug!("{:30} | {:15} | {:30}", path.path(), path.flags().to_string(), path_source);
-            } else {
-                debug!("{:30} | {:15} |", path.path(), path.flags().to_string());
+        resolver = resolver.with_config_file(config_file);
+    }
+    if opts.dedup() {
+        resolver = resolver.with_dedup(DedupMode::KeepFirst);
+    }
+    if opts.strict {
+        resolver = resolver.with_strict();
+    }
+    Ok(resolver)
+}
+
+/// Generates the PATH from the environment and the configuration files
+fn generate(opts: &cli::Opts) -> Result<Generated> {
+    let resolution = match resolver(opts)?.resolve() {
+        Err(ResolveError::MissingRequired(missing)) => {
+            for missing_path in &missing {
+                eprintln!("pathfix: {}", missing_path);
             }
+            return Err(Error::missing_required(missing.len()));
         }
+        result => result?,
+    };
+    for warning in &resolution.warnings {
+        eprintln!("pathfix: warning: {}", warning);
     }
 
-    let include_administrative = config.include_administrative.as_ref()
-        .unwrap_or(&IncludeAdministrative::RootOnly);
+    Ok(Generated {
+        path: resolution.path(),
+        candidates: resolution.candidates(),
+        warnings: resolution.warnings,
+        config: resolution.config,
+        env: resolution.env,
+    })
+}
 
-    let path_flags = PathFlags::this_system(include_administrative);
 
-    let mut path = config.paths.resolve(path_flags, &config.env);
+/// Joins paths for a double quoted value in sudoers or /etc/environment.
+///
+/// Paths which can not be quoted are left out.
+fn quoted_path(path: &[PathBuf]) -> String {
+    let quotable = path.iter().filter_map(|p| match p.to_str() {
+        Some(p) if !p.contains(&['"', '\\', '\n'][..]) => Some(p),
+        _ => {
+            warn!("Skipping {}, because it can not be quoted", p.display());
+            None
+        }
+    });
+    quotable.collect::<Vec<_>>().join(":")
+}
 
-    if opts.dedup() {
-        // removes duplicates while preserving order
-        let (new_path, _set) = path.iter().fold(
-            (Vec::new(), HashSet::new()),
-            |(mut path, mut set), p| {
-                if !set.contains(p) {
-                    set.insert(p);
-                    path.push(p.clone());
-                }
-                (path, set)
-            });
-        path = new_path;
+/// Formats an `export` statement for POSIX shells, the value is quoted in single quotes
+fn export_statement(name: &str, value: &[u8]) -> Vec<u8> {
+    let mut statement = format!("export {}='", name).into_bytes();
+    for &b in value {
+        match b {
+            b'\'' => statement.extend_from_slice(b"'\\''"),
+            _ => statement.push(b),
+        }
     }
+    statement.extend_from_slice(b"'\n");
+    statement
+}
 
-    // filter not existing paths
-    path = path.into_iter().filter(
-        |path| fs::metadata(path).map(
-            |path| path.is_dir())
-            .unwrap_or(false)
-    ).collect();
-
-    debug!("IncludeAdministrative: {:?}", config.include_administrative.clone().unwrap_or_default());
-    debug!("Use admin paths: {:?}", config.include_administrative.clone().unwrap_or_default().check_current_user());
+/// Formats a `KEY=VALUE` line for systemd environment generators,
+/// the value is quoted in double quotes if necessary
+fn environment_d_statement(name: &str, value: &str) -> String {
+    let plain = value.chars()
+        .all(|c| c.is_ascii_alphanumeric() || "/_.:,+=@%-".contains(c));
+    if plain {
+        return format!("{}={}\n", name, value);
+    }
+    let mut statement = format!("{}=\"", name);
+    for c in value.chars() {
+        if let '\\' | '"' | '$' | '`' = c {
+            statement.push('\\');
+        }
+        statement.push(c);
+    }
+    statement.push_str("\"\n");
+    statement
+}
 
-    // print output to stdout
-    if opts.lines {
-        for p in path {
-            println!("{}", p);
+/// Runs a command with the generated PATH and the variables from the configuration.
+///
+/// Returns only if the command could not be executed, or if required paths are missing in strict mode.
+fn exec(opts: &cli::Opts, exec_opts: &cli::ExecOpts) -> i32 {
+    let (path, overrides) = match generate(opts) {
+        Ok(generated) => {
+            // only variables which differ from the environment have to be set
+            let overrides: Vec<_> = generated.env.into_iter()
+                .filter(|(name, value)| name != "PATH" && std::env::var_os(name).as_ref() != Some(value))
+                .collect();
+            (OsString::from_vec(join_paths(&generated.path, b':')), overrides)
         }
-    } else {
-        println!("{}", path.join(":"));
+        // --strict asks to fail instead of running the command without the required paths
+        Err(err) if err.is_missing_required() => {
+            eprintln!("pathfix: {}", err);
+            return err.exit_code();
+        }
+        Err(err) => {
+            eprintln!("pathfix: {}", err);
+            eprintln!("pathfix: Failure. Using included failsave PATH");
+            (OsString::from(FAILSAFE_PATH), Vec::new())
+        }
+    };
+
+    let (program, args) = exec_opts.command.split_first()
+        .expect("clap requires a command");
+    let err = process::Command::new(program)
+        .args(args)
+        .envs(overrides)
+        .env("PATH", path)
+        .exec();
+    eprintln!("pathfix: {}: {}", program.to_string_lossy(), err);
+    match err.kind() {
+        io::ErrorKind::NotFound => 127,
+        _ => 126,
     }
+}
 
+/// Prints the merged configuration with the sources of its settings
+fn dump_config(opts: &cli::Opts) -> Result<()> {
+    // configured values must not be replaced by the current environment
+    let resolver = resolver(opts)?.without_process_env();
+    let config = resolver.load()?;
+    io::stdout().write_all(config.dump(resolver.profile())?.as_bytes())?;
+    Ok(())
+}
+
+/// Converts a configuration file into another format
+fn convert(convert_opts: &cli::ConvertOpts) -> Result<()> {
+    let config = ConfigFileDescription::from_path(&convert_opts.input)?.open()?;
+    let format = match (convert_opts.to, &convert_opts.output) {
+        (Some(format), _) => format,
+        (None, Some(output)) => ConfigFileDescription::from_path(output)?.format(),
+        (None, None) => unreachable!("clap requires --to without an output"),
+    };
+
+    for unsupported in config.unsupported_in(format) {
+        eprintln!("pathfix: warning: {} can not be converted and is skipped", unsupported);
+    }
+    let contents = config.serialize_as(format)?;
+    match &convert_opts.output {
+        Some(output) => std::fs::write(output, contents)
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", output.display(), err)))?,
+        None => io::stdout().write_all(contents.as_bytes())?,
+    }
     Ok(())
 }
 
@@ -137,13 +374,43 @@ fn main() {
             env_logger::init();
         }
 
-    std::process::exit(match run() {
+    let opts = cli::opts();
+
+    if let Some(cli::SubCommand::Exec(exec_opts)) = &opts.subcommand {
+        process::exit(exec(&opts, exec_opts));
+    }
+
+    // there is no PATH to fall back to for these commands
+    let command = match &opts.subcommand {
+        Some(cli::SubCommand::Convert(convert_opts)) => Some(convert(convert_opts)),
+        Some(cli::SubCommand::Config(config_opts)) => match config_opts.action {
+            cli::ConfigAction::Dump => Some(dump_config(&opts)),
+        },
+        _ => None,
+    };
+    if let Some(result) = command {
+        process::exit(match result {
+            Ok(()) => 0,
+            Err(err) => {
+                eprintln!("pathfix: {}", err);
+                err.exit_code()
+            }
+        });
+    }
+
+    process::exit(match run(&opts) {
         Ok(()) => 0,
         Err(err) => {
             eprintln!("pathfix: {}", err);
             eprintln!("pathfix: Failure. Returning included failsave PATH");
-            println!("/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin");
-            1
+            match opts.format() {
+                OutputFormat::Colon | OutputFormat::Lines => println!("{}", FAILSAFE_PATH),
+                OutputFormat::Export => println!("export PATH='{}'", FAILSAFE_PATH),
+                OutputFormat::Systemd => println!("PATH={}", FAILSAFE_PATH),
+                OutputFormat::Sudoers => println!("Defaults secure_path=\"{}\"", FAILSAFE_PATH),
+                OutputFormat::EtcEnvironment => println!("PATH=\"{}\"", FAILSAFE_PATH),
+            }
+            err.exit_code()
         }
     });
 }
@@ -0,0 +1,404 @@
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::expand;
+
+const DEFAULT_TIMEOUT_MS: u64 = 1000;
+
+/// Command whose output is the value of an environment variable
+///
+/// Arguments are expanded like paths, the program is searched in `$PATH`
+/// if it does not contain a `/`.
+/// The command inherits the environment of pathfix, overridden by the variables resolved so far.
+/// The trimmed standard output is used as value, a command which fails or
+/// does not finish within the timeout leaves the variable unset.
+///
+/// ```toml
+/// [env]
+/// GOPATH = { command = ["go", "env", "GOPATH"], timeout_ms = 500 }
+/// ```
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct EnvCommand {
+    command: Vec<String>,
+    timeout_ms: u64,
+    cache: bool,
+}
+
+impl EnvCommand {
+    /// Creates a command with the default timeout, whose output is cached
+    pub fn new<S: ToString>(command: &[S]) -> EnvCommand {
+        EnvCommand {
+            command: command.iter().map(ToString::to_string).collect(),
+            timeout_ms: DEFAULT_TIMEOUT_MS,
+            cache: true,
+        }
+    }
+
+    /// Sets the time after which the command will be killed
+    pub fn with_timeout(self, timeout: Duration) -> EnvCommand {
+        EnvCommand {
+            timeout_ms: timeout.as_millis() as u64,
+            ..self
+        }
+    }
+
+    /// Sets if the output of the command may be cached
+    pub fn with_cache(self, cache: bool) -> EnvCommand {
+        EnvCommand {
+            cache,
+            ..self
+        }
+    }
+
+    /// Returns the program and its arguments
+    pub fn command(&self) -> &[String] {
+        &self.command
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+
+    pub fn cache(&self) -> bool {
+        self.cache
+    }
+
+    /// Runs the command, or takes its output from the `cache` if the program did not change.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use pathfix::config::{CommandCache, EnvCommand};
+    ///
+    /// let mut env = HashMap::new();
+    /// env.insert("GREETING".to_string(), "hello".into());
+    /// let command = EnvCommand::new(&["/bin/echo", "$GREETING"]);
+    ///
+    /// assert_eq!(command.run(&env, &mut CommandCache::disabled()).unwrap(), "hello");
+    /// ```
+    pub fn run(&self, env: &HashMap<String, OsString>, cache: &mut CommandCache) -> io::Result<OsString> {
+        let args = self.command.iter()
+            .map(|arg| expand(arg, env))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        let (program, args) = args.split_first()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "command is empty"))?;
+        let path = env.get("PATH").cloned().or_else(|| std::env::var_os("PATH"));
+        let program = find_program(program, path.as_deref())
+            .ok_or_else(|| io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("program {} not found", program.to_string_lossy()),
+            ))?;
+        let mtime = fs::metadata(&program)?
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        // The cache file is TOML, so only commands and outputs which are valid UTF-8 can be cached
+        let key = match (program.to_str(), args.iter().map(|arg| arg.to_str()).collect::<Option<Vec<_>>>()) {
+            (Some(program), Some(args)) if self.cache => Some(CacheEntry {
+                program: program.to_string(),
+                args: args.into_iter().map(str::to_string).collect(),
+                mtime: mtime.as_nanos().to_string(),
+                value: String::new(),
+            }),
+            _ => None,
+        };
+        if let Some(value) = key.as_ref().and_then(|key| cache.get(key)) {
+            debug!("Using cached output of {}", program.to_string_lossy());
+            return Ok(value.into());
+        }
+
+        let value = run_with_timeout(&program, args, env, self.timeout())?;
+        if let (Some(key), Some(output)) = (key, value.to_str()) {
+            cache.insert(CacheEntry { value: output.to_string(), ..key });
+        }
+        Ok(value)
+    }
+}
+
+/// Searches `program` in the colon separated `path`, if it is not a path itself.
+fn find_program(program: &OsStr, path: Option<&OsStr>) -> Option<PathBuf> {
+    if program.as_bytes().contains(&b'/') {
+        return Some(PathBuf::from(program));
+    }
+    path?.as_bytes().split(|&b| b == b':')
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| Path::new(OsStr::from_bytes(dir)).join(program))
+        .find(|candidate| candidate.is_file())
+}
+
+fn run_with_timeout(
+    program: &Path,
+    args: &[OsString],
+    env: &HashMap<String, OsString>,
+    timeout: Duration,
+) -> io::Result<OsString> {
+    let mut child = Command::new(program)
+        .args(args)
+        .envs(env)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+
+    let mut stdout = child.stdout.take().unwrap();
+    // a background process of the command may keep its output open, so reading is bounded as well
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut output = Vec::new();
+        let _ = sender.send(stdout.read_to_end(&mut output).map(|_| output));
+    });
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            child.kill()?;
+            child.wait()?;
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("{} did not finish within {:?}", program.to_string_lossy(), timeout),
+            ));
+        }
+        thread::sleep(Duration::from_millis(5));
+    };
+
+    let output = match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+        Ok(output) => output?,
+        Err(RecvTimeoutError::Timeout) => return Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("output of {} was not closed within {:?}", program.to_string_lossy(), timeout),
+        )),
+        Err(RecvTimeoutError::Disconnected) => return Err(io::Error::other("could not read command output")),
+    };
+    if !status.success() {
+        return Err(io::Error::other(
+            format!("{} failed with {}", program.to_string_lossy(), status),
+        ));
+    }
+    let output = output.trim_ascii();
+    Ok(OsString::from_vec(output.to_vec()))
+}
+
+/// Cached outputs of `EnvCommand`s
+///
+/// Outputs are keyed on the program, its arguments and the modification time of the program.
+#[derive(Debug, Default)]
+pub struct CommandCache {
+    location: Option<PathBuf>,
+    entries: Vec<CacheEntry>,
+    changed: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
+struct CacheEntry {
+    program: String,
+    args: Vec<String>,
+    mtime: String,
+    value: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CacheFile {
+    #[serde(default)]
+    command: Vec<CacheEntry>,
+}
+
+impl CommandCache {
+    /// Creates a cache which does not store anything
+    pub fn disabled() -> CommandCache {
+        Default::default()
+    }
+
+    /// Opens the cache file at `location`.
+    ///
+    /// A missing or broken cache file results in an empty cache.
+    pub fn open<P: Into<PathBuf>>(location: P) -> CommandCache {
+        let location = location.into();
+        let entries = fs::read(&location).ok()
+            .and_then(|contents| toml::from_slice::<CacheFile>(&contents).ok())
+            .map(|file| file.command)
+            .unwrap_or_default();
+        CommandCache {
+            location: Some(location),
+            entries,
+            changed: false,
+        }
+    }
+
+    /// Returns the default cache file location in `$XDG_CACHE_HOME/pathfix` or `~/.cache/pathfix`.
+    pub fn default_location() -> Option<PathBuf> {
+        cache_dir().map(|dir| dir.join("commands.toml"))
+    }
+
+    fn get(&self, key: &CacheEntry) -> Option<&str> {
+        self.entries.iter()
+            .find(|entry| entry.program == key.program && entry.args == key.args && entry.mtime == key.mtime)
+            .map(|entry| entry.value.as_str())
+    }
+
+    fn insert(&mut self, entry: CacheEntry) {
+        if self.location.is_none() {
+            return;
+        }
+        self.entries.retain(|e| e.program != entry.program || e.args != entry.args);
+        self.entries.push(entry);
+        self.changed = true;
+    }
+
+    /// Writes the cache back to its file, if it was changed.
+    pub fn save(&self) -> io::Result<()> {
+        let location = match &self.location {
+            Some(location) if self.changed => location,
+            _ => return Ok(()),
+        };
+        if let Some(parent) = location.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let contents = toml::to_string(&CacheFile { command: self.entries.clone() })
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let tmp = location.with_extension("tmp");
+        io::Write::write_all(&mut File::create(&tmp)?, contents.as_bytes())?;
+        fs::rename(tmp, location)
+    }
+}
+
+/// Returns the cache directory of pathfix in `$XDG_CACHE_HOME/pathfix` or `~/.cache/pathfix`.
+pub(crate) fn cache_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .map(|dir| dir.join("pathfix"))
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct EnvCommandDef {
+    pub(crate) command: Vec<String>,
+    #[serde(default = "default_timeout_ms")]
+    pub(crate) timeout_ms: u64,
+    #[serde(default = "default_cache")]
+    pub(crate) cache: bool,
+}
+
+fn default_timeout_ms() -> u64 {
+    DEFAULT_TIMEOUT_MS
+}
+
+fn default_cache() -> bool {
+    true
+}
+
+impl From<EnvCommandDef> for EnvCommand {
+    fn from(def: EnvCommandDef) -> Self {
+        EnvCommand {
+            command: def.command,
+            timeout_ms: def.timeout_ms,
+            cache: def.cache,
+        }
+    }
+}
+
+impl From<&EnvCommand> for EnvCommandDef {
+    fn from(command: &EnvCommand) -> Self {
+        EnvCommandDef {
+            command: command.command.clone(),
+            timeout_ms: command.timeout_ms,
+            cache: command.cache,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::ffi::OsString;
+    use std::time::{Duration, Instant};
+
+    use crate::config::{CommandCache, EnvCommand};
+
+    fn env() -> HashMap<String, OsString> {
+        [("PATH", "/usr/bin:/bin"), ("NAME", "pathfix")]
+            .iter()
+            .map(|(k, v)| (k.to_string(), OsString::from(v)))
+            .collect()
+    }
+
+    #[test]
+    fn test_run() {
+        let command = EnvCommand::new(&["echo", "  hello $NAME  "]);
+        assert_eq!(command.run(&env(), &mut CommandCache::disabled()).unwrap(), "hello pathfix");
+    }
+
+    #[test]
+    fn test_failure() {
+        let cache = &mut CommandCache::disabled();
+        assert!(EnvCommand::new(&["false"]).run(&env(), cache).is_err());
+        assert!(EnvCommand::new(&["thiscommanddoesnotexist"]).run(&env(), cache).is_err());
+        assert!(EnvCommand::new(&["echo", "$UNSET"]).run(&env(), cache).is_err());
+        let empty: [&str; 0] = [];
+        assert!(EnvCommand::new(&empty).run(&env(), cache).is_err());
+    }
+
+    #[test]
+    fn test_timeout() {
+        let command = EnvCommand::new(&["sleep", "5"]).with_timeout(Duration::from_millis(50));
+        let err = command.run(&env(), &mut CommandCache::disabled()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn test_timeout_background_output() {
+        let command = EnvCommand::new(&["sh", "-c", "sleep 5 & echo started"])
+            .with_timeout(Duration::from_millis(200));
+        let start = Instant::now();
+        let err = command.run(&env(), &mut CommandCache::disabled()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_inherited_env() {
+        let command = EnvCommand::new(&["printenv", "CARGO_PKG_NAME"]);
+        assert_eq!(command.run(&env(), &mut CommandCache::disabled()).unwrap(), "pathfix");
+
+        let command = EnvCommand::new(&["printenv", "HOME"]);
+        let mut env = env();
+        env.insert("HOME".to_string(), "/home/resolved".into());
+        assert_eq!(command.run(&env, &mut CommandCache::disabled()).unwrap(), "/home/resolved");
+    }
+
+    #[test]
+    fn test_cache() {
+        let dir = std::env::temp_dir().join(format!("pathfix-test-cache-{}", std::process::id()));
+        let location = dir.join("commands.toml");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let command = EnvCommand::new(&["sh", "-c", "echo $$$$"]);
+        let mut cache = CommandCache::open(&location);
+        let first = command.run(&env(), &mut cache).unwrap();
+        cache.save().unwrap();
+
+        let mut cache = CommandCache::open(&location);
+        assert_eq!(command.run(&env(), &mut cache).unwrap(), first);
+
+        let uncached = command.with_cache(false);
+        assert_ne!(uncached.run(&env(), &mut cache).unwrap(), first);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,11 +1,17 @@
+use std::env::VarError;
+use std::ffi::OsStr;
 use std::fmt::{self, Display, Formatter};
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
-use crate::config::Config;
-use std::env::VarError;
-use std::ffi::OsStr;
+use crate::config::{Config, FileSystem, RealFileSystem, SystemProfile};
+
+/// File which is read by macOS `path_helper`, in addition to the files in `/etc/paths.d`
+pub const PATH_HELPER_FILE: &str = "/etc/paths";
+
+/// Directory of files which are read by macOS `path_helper`
+pub const PATH_HELPER_DIR: &str = "/etc/paths.d";
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum ConfigSource {
@@ -14,37 +20,46 @@ pub enum ConfigSource {
     System(ConfigFileFormat),
     Home(ConfigFileFormat),
     Config(ConfigFileDescription),
+    /// `/etc/paths` and the files in `/etc/paths.d`, like macOS `path_helper` reads them
+    PathHelper,
 }
 
 impl ConfigSource {
     pub fn open(self) -> io::Result<Config> {
+        self.open_for(&SystemProfile::this_system())
+    }
+
+    /// Opens the configuration of the user and root directory of `profile`
+    pub fn open_for(self, profile: &SystemProfile) -> io::Result<Config> {
         let mut config = match self {
+            // each file keeps its own source
+            ConfigSource::PathHelper => return Config::from_path_helper(profile),
             ConfigSource::PathVar => Config::new().with_env(),
             ConfigSource::Included => Config::included(),
             ConfigSource::System(_) | ConfigSource::Home(_) | ConfigSource::Config(_) => self
-                .file_description()
+                .file_description(profile)
                 .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?
                 .unwrap()
-                .open()?,
+                .open_with(profile.file_system())?,
         };
-        config.paths.set_source(self);
+        config.set_source(self);
         Ok(config)
     }
 
     /// Gets the file description, if the configuration source describes a file on the file system.
-    fn file_description(&self) -> Result<Option<ConfigFileDescription>, VarError> {
+    pub(crate) fn file_description(&self, profile: &SystemProfile) -> Result<Option<ConfigFileDescription>, VarError> {
         match self {
+            ConfigSource::System(ConfigFileFormat::Paths) => Ok(Some(ConfigFileDescription::new(
+                profile.in_root(Path::new(PATH_HELPER_FILE)),
+                ConfigFileFormat::Paths,
+            ))),
             ConfigSource::System(format) => Ok(Some(ConfigFileDescription::new(
-                format!("/etc/pathfix.{}", format.extension()),
+                profile.in_root(Path::new(&format!("/etc/pathfix.{}", format.extension()))),
                 *format,
             ))),
             ConfigSource::Home(format) => {
-                let path: PathBuf = [
-                    &std::env::var("HOME")?,
-                    &format!(".pathfix.{}", format.extension()),
-                ]
-                .iter()
-                .collect();
+                let home = profile.home().ok_or(VarError::NotPresent)?;
+                let path = profile.in_root(&home).join(format!(".pathfix.{}", format.extension()));
                 Ok(Some(ConfigFileDescription::new(path, *format)))
             }
             ConfigSource::Config(description) => Ok(Some(description.clone())),
@@ -61,6 +76,7 @@ impl Display for ConfigSource {
             ConfigSource::System(_) => write!(f, "from system dir"),
             ConfigSource::Home(_) => write!(f, "from home dir"),
             ConfigSource::Config(description) => write!(f, "config: {}", description),
+            ConfigSource::PathHelper => write!(f, "from {}", PATH_HELPER_FILE),
         }
     }
 }
@@ -79,10 +95,36 @@ impl ConfigFileDescription {
         }
     }
 
+    /// Describes the file at `location` like `from_str`, but defaults to TOML if it has no extension
+    pub fn from_path<P: Into<PathBuf>>(location: P) -> io::Result<ConfigFileDescription> {
+        let location = location.into();
+        match location.to_string_lossy().parse() {
+            Err(_) if location.extension().is_none() => Ok(ConfigFileDescription::new(location, ConfigFileFormat::Toml)),
+            description => description,
+        }
+    }
+
+    pub fn location(&self) -> &Path {
+        &self.location
+    }
+
+    pub fn format(&self) -> ConfigFileFormat {
+        self.format
+    }
+
     pub fn open(&self) -> io::Result<Config> {
+        self.open_with(&RealFileSystem)
+    }
+
+    pub fn open_with(&self, file_system: &dyn FileSystem) -> io::Result<Config> {
         match self.format {
-            ConfigFileFormat::Toml => Config::from_file(&self.location),
-            ConfigFileFormat::Text => Config::from_txt(&self.location),
+            ConfigFileFormat::Toml => Config::from_file_with(&self.location, file_system),
+            ConfigFileFormat::Text => Config::from_txt_with(&self.location, file_system),
+            ConfigFileFormat::Paths => Config::from_paths_file_with(&self.location, file_system),
+            #[cfg(feature = "json")]
+            ConfigFileFormat::Json => Config::from_json_with(&self.location, file_system),
+            #[cfg(feature = "yaml")]
+            ConfigFileFormat::Yaml => Config::from_yaml_with(&self.location, file_system),
         }
     }
 }
@@ -99,29 +141,41 @@ impl FromStr for ConfigFileDescription {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let location = PathBuf::from(s);
 
-        Ok(match location.extension().and_then(OsStr::to_str) {
-            Some("txt") => ConfigFileDescription::new(location, ConfigFileFormat::Text),
-            Some("toml") => ConfigFileDescription::new(location, ConfigFileFormat::Toml),
-            Some(extension) => {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    format!("File extension {} not known", extension),
-                ))
-            }
-            _ => {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    "Config file needs either 'toml' or 'txt' as file format",
-                ))
-            }
-        })
+        // files of `path_helper` usually have no extension, e.g. `/etc/paths.d/TeX`
+        let path_helper_file = location.extension().is_none() && location.parent()
+            .and_then(Path::file_name)
+            .is_some_and(|dir| dir == "paths.d");
+        if location.file_name().is_some_and(|name| name == "paths") || path_helper_file {
+            return Ok(ConfigFileDescription::new(location, ConfigFileFormat::Paths));
+        }
+
+        match location.extension().and_then(OsStr::to_str) {
+            Some(extension) => match extension.parse() {
+                Ok(format) => Ok(ConfigFileDescription::new(location, format)),
+                Err(err) => Err(io::Error::new(err.kind(), format!("{}: {}", location.display(), err))),
+            },
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Config file needs either {} as file format", FORMAT_NAMES),
+            )),
+        }
     }
 }
 
+/// Extensions of all configuration formats, for error messages
+const FORMAT_NAMES: &str = "'toml', 'txt', 'paths', 'json' or 'yaml'";
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
 pub enum ConfigFileFormat {
     Toml,
     Text,
+    /// One directory per line, as in `/etc/paths`
+    Paths,
+    #[cfg(feature = "json")]
+    Json,
+    #[cfg(feature = "yaml")]
+    Yaml,
 }
 
 impl ConfigFileFormat {
@@ -129,6 +183,44 @@ impl ConfigFileFormat {
         match self {
             ConfigFileFormat::Toml => "toml",
             ConfigFileFormat::Text => "txt",
+            ConfigFileFormat::Paths => "paths",
+            #[cfg(feature = "json")]
+            ConfigFileFormat::Json => "json",
+            #[cfg(feature = "yaml")]
+            ConfigFileFormat::Yaml => "yaml",
+        }
+    }
+}
+
+impl FromStr for ConfigFileFormat {
+    type Err = io::Error;
+
+    /// Parses the name or file extension of a format, e.g. `toml` or `yml`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "toml" => Ok(ConfigFileFormat::Toml),
+            "txt" => Ok(ConfigFileFormat::Text),
+            "paths" => Ok(ConfigFileFormat::Paths),
+            #[cfg(feature = "json")]
+            "json" => Ok(ConfigFileFormat::Json),
+            #[cfg(feature = "yaml")]
+            "yaml" | "yml" => Ok(ConfigFileFormat::Yaml),
+            #[cfg(not(feature = "json"))]
+            "json" => Err(disabled_format("json")),
+            #[cfg(not(feature = "yaml"))]
+            "yaml" | "yml" => Err(disabled_format("yaml")),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("File extension {} not known, use {}", s, FORMAT_NAMES),
+            )),
         }
     }
 }
+
+#[cfg(any(not(feature = "json"), not(feature = "yaml")))]
+fn disabled_format(feature: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{} configurations need pathfix to be built with the '{}' feature", feature, feature),
+    )
+}
@@ -0,0 +1,176 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write;
+use std::io;
+use std::sync::Arc;
+
+use serde::Serialize;
+
+use crate::config::{inline_toml, toml_key, Config, ConfigSource, PathsStyle, SystemProfile};
+
+impl Config {
+    /// Writes the config as TOML, with a comment naming the source of every setting.
+    ///
+    /// Variables of the process environment and the entries of `$PATH` are left out,
+    /// so the result can be used as a configuration file again. Sources are described with their files below
+    /// the root of `profile`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use pathfix::config::{Config, ConfigSource, SystemProfile};
+    ///
+    /// let mut config: Config = toml::from_str("base = true\npaths = [\"~/bin\"]").unwrap();
+    /// config.set_source(ConfigSource::Included);
+    ///
+    /// let dump = config.dump(&SystemProfile::this_system()).unwrap();
+    /// assert_eq!(dump, "# included in binary\nbase = true\n\n[paths]\n# included in binary\n\"~/bin\" = \"any\"\n");
+    /// ```
+    pub fn dump(&self, profile: &SystemProfile) -> io::Result<String> {
+        let mut document = String::new();
+
+        comment(&mut document, self.base_source(), profile);
+        writeln!(document, "base = {}", self.base).unwrap();
+        if self.export_env {
+            writeln!(document, "export_env = true").unwrap();
+        }
+        if let Some(include_administrative) = &self.include_administrative {
+            comment(&mut document, self.include_administrative_source(), profile);
+            writeln!(document, "include_administrative = {}", inline_toml(&to_value(include_administrative)?)).unwrap();
+        }
+
+        let paths: Vec<_> = self.paths.iter()
+            .filter(|path| !matches!(path.source().map(AsRef::as_ref), Some(ConfigSource::PathVar)))
+            .collect();
+
+        // the same directory can come from several sources, but keys of a map have to be unique
+        let mut keys = HashSet::new();
+        let unique = paths.iter().all(|path| keys.insert(path.path()));
+        if self.paths.style() == PathsStyle::Map && unique {
+            writeln!(document, "\n[paths]").unwrap();
+            for path in &paths {
+                let key = path.path().to_str().ok_or_else(|| invalid_data(format!(
+                    "path {} is not valid UTF-8", path.path().to_string_lossy()
+                )))?;
+                comment(&mut document, path.source(), profile);
+                writeln!(document, "{} = {}", toml_key(key), inline_toml(&to_value(path.flags())?)).unwrap();
+            }
+        } else {
+            for path in &paths {
+                writeln!(document).unwrap();
+                comment(&mut document, path.source(), profile);
+                writeln!(document, "[[paths]]").unwrap();
+                table(&mut document, to_value(path)?, Some("path"));
+            }
+        }
+
+        for exclude in &self.exclude.0 {
+            writeln!(document).unwrap();
+            comment(&mut document, exclude.source(), profile);
+            writeln!(document, "[[exclude]]").unwrap();
+            table(&mut document, to_value(exclude)?, None);
+        }
+
+        let env: BTreeMap<_, _> = self.env.iter()
+            .filter(|(_, value)| !value.is_literal())
+            .collect();
+        if !env.is_empty() {
+            writeln!(document, "\n[env]").unwrap();
+            for (name, value) in env {
+                comment(&mut document, value.source(), profile);
+                writeln!(document, "{} = {}", toml_key(name), inline_toml(&to_value(value)?)).unwrap();
+            }
+        }
+
+        Ok(document)
+    }
+}
+
+/// Writes a comment naming `source` and its file
+fn comment(document: &mut String, source: Option<&Arc<ConfigSource>>, profile: &SystemProfile) {
+    let source = match source {
+        Some(source) => source,
+        None => return,
+    };
+    match source.file_description(profile) {
+        // the description of a config already names its file
+        Ok(Some(description)) if !matches!(**source, ConfigSource::Config(_)) => {
+            writeln!(document, "# {}: {}", source, description).unwrap()
+        }
+        _ => writeln!(document, "# {}", source).unwrap(),
+    }
+}
+
+/// Writes the entries of a table, starting with `first`
+fn table(document: &mut String, value: toml::Value, first: Option<&str>) {
+    let table = match value {
+        toml::Value::Table(table) => table,
+        _ => return,
+    };
+    let (first, rest): (Vec<_>, Vec<_>) = table.iter().partition(|(key, _)| Some(key.as_str()) == first);
+    for (key, value) in first.into_iter().chain(rest) {
+        writeln!(document, "{} = {}", toml_key(key), inline_toml(value)).unwrap();
+    }
+}
+
+fn to_value<T: Serialize>(value: T) -> io::Result<toml::Value> {
+    toml::Value::try_from(value).map_err(|err| invalid_data(err.to_string()))
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{
+        Config, ConfigFileDescription, ConfigFileFormat, ConfigSource, EnvValue, Paths, SystemProfile,
+    };
+
+    #[test]
+    fn test_dump() {
+        let mut system: Config = toml::from_str(r#"
+            base = true
+            include_administrative = { any = [ { groups = ["wheel"] }, { uid-range = { min = 1000 } } ] }
+            paths = ["/usr/bin", { path = "/opt/corp/bin", required = true }]
+
+            [exclude]
+            literal = ["."]
+
+            [env]
+            GOROOT = "/usr/local/go"
+        "#).unwrap();
+        system.set_source(ConfigSource::System(ConfigFileFormat::Toml));
+        let mut file: Config = toml::from_str(r#"
+            paths = ["/usr/bin"]
+
+            [env]
+            GOPATH = { value = "$HOME/go", export = true }
+        "#).unwrap();
+        file.set_source(ConfigSource::Config(ConfigFileDescription::new("/home/alice/extra.toml", ConfigFileFormat::Toml)));
+        let mut process = Config::new();
+        process.env.insert("HOME".to_string(), EnvValue::literal("/home/alice"));
+        let mut path_var: Config = toml::from_str(r#"paths = ["/home/alice/.path-var/bin"]"#).unwrap();
+        path_var.set_source(ConfigSource::PathVar);
+        let merged = file.merge(path_var.merge(system.merge(process)));
+
+        let dump = merged.dump(&SystemProfile::this_system().with_root("/srv")).unwrap();
+        assert!(dump.starts_with("# from system dir: /srv/etc/pathfix.toml\nbase = true\n"), "{}", dump);
+        assert!(dump.contains("# config: /home/alice/extra.toml\n[[paths]]\npath = \"/usr/bin\"\n"), "{}", dump);
+        assert!(dump.contains("# config: /home/alice/extra.toml\nGOPATH = { export = true, value = \"$HOME/go\" }\n"), "{}", dump);
+        assert!(!dump.contains("HOME ="), "{}", dump);
+        assert!(!dump.contains(".path-var"), "{}", dump);
+        assert!(!dump.contains("PATH variable"), "{}", dump);
+
+        let parsed: Config = toml::from_str(&dump).unwrap();
+        assert_eq!(parsed.base, merged.base);
+        assert_eq!(parsed.include_administrative, merged.include_administrative);
+        let configured: Paths = merged.paths.iter()
+            .filter(|path| path.source().map(AsRef::as_ref) != Some(&ConfigSource::PathVar))
+            .cloned()
+            .collect();
+        assert_eq!(parsed.paths, configured.normalize());
+        assert_eq!(parsed.exclude.0.len(), 1);
+        assert_eq!(parsed.env.len(), 2);
+        assert_eq!(parsed.env["GOPATH"], EnvValue::new("$HOME/go").exported());
+    }
+}
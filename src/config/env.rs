@@ -0,0 +1,507 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fmt;
+use std::sync::Arc;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::ser::{Error as _, SerializeMap};
+use thiserror::Error;
+
+use crate::config::{expand_with, references, CommandCache, ConfigSource, EnvCommand, ExpandError, SystemUsers, UserLookup};
+use crate::config::command::EnvCommandDef;
+
+/// Value of a variable in the `[env]` table
+///
+/// Values can refer to other variables with the same syntax as paths,
+/// e.g. `GOPATH = "$HOME/go"`, or be the output of a command (see `EnvCommand`).
+/// Variables from the process environment are used as they are and take
+/// precedence over configured values, unless the value is set with `override = true`.
+/// Values with `export = true` are exported to the shell by `pathfix --export`:
+///
+/// ```toml
+/// [env]
+/// GOROOT = "/usr/local/go"                      # only if GOROOT is not set
+/// GOPATH = { value = "$HOME/go", override = true, export = true }
+/// BREW_PREFIX = { command = ["brew", "--prefix"] }
+/// ```
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct EnvValue {
+    kind: EnvKind,
+    overrides: bool,
+    export: bool,
+    source: Option<Arc<ConfigSource>>,
+}
+
+/// Where the value of an environment variable comes from
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum EnvKind {
+    /// Configured value, which may refer to other variables
+    Value(String),
+    /// Value from the process environment, which is not necessarily valid UTF-8
+    Literal(OsString),
+    Command(EnvCommand),
+}
+
+impl EnvValue {
+    /// Creates a configured value, which may refer to other variables
+    pub fn new<S: Into<String>>(value: S) -> EnvValue {
+        EnvValue {
+            kind: EnvKind::Value(value.into()),
+            overrides: false,
+            export: false,
+            source: None,
+        }
+    }
+
+    /// Creates a value, which is the output of a command
+    pub fn command(command: EnvCommand) -> EnvValue {
+        EnvValue {
+            kind: EnvKind::Command(command),
+            overrides: false,
+            export: false,
+            source: None,
+        }
+    }
+
+    /// Creates a value from the process environment, which will not be expanded
+    pub fn literal<S: Into<OsString>>(value: S) -> EnvValue {
+        EnvValue {
+            kind: EnvKind::Literal(value.into()),
+            overrides: false,
+            export: false,
+            source: None,
+        }
+    }
+
+    /// Marks the value to take precedence over the process environment
+    pub fn overriding(self) -> EnvValue {
+        EnvValue {
+            overrides: true,
+            ..self
+        }
+    }
+
+    /// Marks the value to be exported to the shell
+    pub fn exported(self) -> EnvValue {
+        EnvValue {
+            export: true,
+            ..self
+        }
+    }
+
+    /// Returns the unexpanded value, if it is valid UTF-8 and not the output of a command
+    pub fn value(&self) -> Option<&str> {
+        match &self.kind {
+            EnvKind::Value(value) => Some(value),
+            EnvKind::Literal(value) => value.to_str(),
+            EnvKind::Command(_) => None,
+        }
+    }
+
+    pub fn kind(&self) -> &EnvKind {
+        &self.kind
+    }
+
+    /// Returns the names of all variables the value refers to
+    pub(crate) fn references(&self) -> Vec<String> {
+        match &self.kind {
+            EnvKind::Value(value) => references(value),
+            EnvKind::Literal(_) => Vec::new(),
+            EnvKind::Command(command) => command.command().iter()
+                .flat_map(|arg| references(arg))
+                .chain(std::iter::once("PATH".to_string()))
+                .collect(),
+        }
+    }
+
+    /// Returns if the value takes precedence over the process environment
+    pub fn overrides(&self) -> bool {
+        self.overrides
+    }
+
+    /// Returns if the value should be exported to the shell
+    pub fn export(&self) -> bool {
+        self.export
+    }
+
+    /// Returns the source of where the value originates from
+    pub fn source(&self) -> Option<&Arc<ConfigSource>> {
+        self.source.as_ref()
+    }
+
+    pub(crate) fn set_source(&mut self, source: Arc<ConfigSource>) {
+        self.source = Some(source);
+    }
+
+    /// Returns if the value is used verbatim
+    pub fn is_literal(&self) -> bool {
+        matches!(self.kind, EnvKind::Literal(_))
+    }
+
+    /// Selects the value of a variable which is defined in two merged configs.
+    ///
+    /// `other` is preferred, unless only `self` overrides.
+    pub fn merge(self, other: EnvValue) -> EnvValue {
+        if self.overrides && !other.overrides {
+            self
+        } else {
+            other
+        }
+    }
+}
+
+impl From<&str> for EnvValue {
+    fn from(s: &str) -> Self {
+        EnvValue::new(s)
+    }
+}
+
+impl From<String> for EnvValue {
+    fn from(s: String) -> Self {
+        EnvValue::new(s)
+    }
+}
+
+impl PartialEq<&str> for EnvValue {
+    fn eq(&self, other: &&str) -> bool {
+        self.value() == Some(*other)
+    }
+}
+
+impl fmt::Display for EnvValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            EnvKind::Value(value) => write!(f, "{}", value),
+            EnvKind::Literal(value) => write!(f, "{}", value.to_string_lossy()),
+            EnvKind::Command(command) => write!(f, "$({})", command.command().join(" ")),
+        }
+    }
+}
+
+impl Serialize for EnvValue {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where
+        S: Serializer,
+    {
+        match &self.kind {
+            EnvKind::Value(value) => self.serialize_value(value, serializer),
+            EnvKind::Literal(value) => self.serialize_value(
+                value.to_str().ok_or_else(|| S::Error::custom(format!(
+                    "value {} is not valid UTF-8", value.to_string_lossy()
+                )))?,
+                serializer,
+            ),
+            EnvKind::Command(command) => {
+                let mut serialize_map = serializer.serialize_map(None)?;
+                let def = EnvCommandDef::from(command);
+                serialize_map.serialize_entry("command", &def.command)?;
+                serialize_map.serialize_entry("timeout_ms", &def.timeout_ms)?;
+                serialize_map.serialize_entry("cache", &def.cache)?;
+                if self.overrides {
+                    serialize_map.serialize_entry("override", &true)?;
+                }
+                if self.export {
+                    serialize_map.serialize_entry("export", &true)?;
+                }
+                serialize_map.end()
+            }
+        }
+    }
+}
+
+impl EnvValue {
+    fn serialize_value<S>(&self, value: &str, serializer: S) -> Result<S::Ok, S::Error> where
+        S: Serializer,
+    {
+        if self.overrides || self.export {
+            let mut serialize_map = serializer.serialize_map(None)?;
+            serialize_map.serialize_entry("value", value)?;
+            if self.overrides {
+                serialize_map.serialize_entry("override", &true)?;
+            }
+            if self.export {
+                serialize_map.serialize_entry("export", &true)?;
+            }
+            serialize_map.end()
+        } else {
+            serializer.serialize_str(value)
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum EnvValueDef {
+    Plain(String),
+    Table {
+        value: String,
+        #[serde(default, rename = "override")]
+        overrides: bool,
+        #[serde(default)]
+        export: bool,
+    },
+    Command {
+        #[serde(flatten)]
+        command: EnvCommandDef,
+        #[serde(default, rename = "override")]
+        overrides: bool,
+        #[serde(default)]
+        export: bool,
+    },
+}
+
+impl<'de> Deserialize<'de> for EnvValue {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where
+        D: Deserializer<'de> {
+        Ok(match EnvValueDef::deserialize(deserializer)? {
+            EnvValueDef::Plain(value) => EnvValue::new(value),
+            EnvValueDef::Table { value, overrides, export } => EnvValue {
+                overrides,
+                export,
+                ..EnvValue::new(value)
+            },
+            EnvValueDef::Command { command, overrides, export } => EnvValue {
+                overrides,
+                export,
+                ..EnvValue::command(command.into())
+            },
+        })
+    }
+}
+
+/// Merges two environments, values in `other` take precedence unless only `env` overrides.
+pub(crate) fn merge_env(env: HashMap<String, EnvValue>, other: HashMap<String, EnvValue>) -> HashMap<String, EnvValue> {
+    let mut merged = env;
+    for (name, value) in other {
+        let value = match merged.remove(&name) {
+            Some(old) => old.merge(value),
+            None => value,
+        };
+        merged.insert(name, value);
+    }
+    merged
+}
+
+/// Expands all values in `env` against each other in dependency order.
+///
+/// Variables whose values refer to unset variables are left out,
+/// as well as variables whose command fails.
+/// Variables which refer to each other result in an `EnvError::Cycle`.
+/// Outputs of commands are not cached, see `resolve_env_cached`.
+///
+/// # Examples
+///
+/// ```
+/// use std::collections::HashMap;
+/// use pathfix::config::{resolve_env, EnvValue};
+///
+/// let mut env = HashMap::new();
+/// env.insert("HOME".to_string(), EnvValue::literal("/home/user"));
+/// env.insert("GOPATH".to_string(), EnvValue::new("$HOME/go"));
+/// env.insert("GOBIN".to_string(), EnvValue::new("$GOPATH/bin"));
+///
+/// assert_eq!(resolve_env(&env).unwrap()["GOBIN"], "/home/user/go/bin");
+/// ```
+pub fn resolve_env(env: &HashMap<String, EnvValue>) -> EnvResult<HashMap<String, OsString>> {
+    resolve_env_cached(env, &mut CommandCache::disabled())
+}
+
+/// Expands all values in `env` like `resolve_env` and uses `cache` for outputs of commands.
+pub fn resolve_env_cached(
+    env: &HashMap<String, EnvValue>,
+    cache: &mut CommandCache,
+) -> EnvResult<HashMap<String, OsString>> {
+    resolve_env_with(env, cache, &SystemUsers)
+}
+
+/// Like `resolve_env_cached`, but looks up the home directories of `~user` in `users`.
+///
+/// Commands run on the running system, so their arguments are expanded with its users.
+pub fn resolve_env_with(
+    env: &HashMap<String, EnvValue>,
+    cache: &mut CommandCache,
+    users: &dyn UserLookup,
+) -> EnvResult<HashMap<String, OsString>> {
+    let mut resolver = EnvResolver {
+        env,
+        cache,
+        users,
+        resolved: HashMap::new(),
+        unresolvable: HashSet::new(),
+        stack: Vec::new(),
+    };
+    let mut names: Vec<&String> = env.keys().collect();
+    names.sort();
+    for name in names {
+        resolver.resolve(name)?;
+    }
+    Ok(resolver.resolved)
+}
+
+struct EnvResolver<'a> {
+    env: &'a HashMap<String, EnvValue>,
+    cache: &'a mut CommandCache,
+    users: &'a dyn UserLookup,
+    resolved: HashMap<String, OsString>,
+    unresolvable: HashSet<String>,
+    stack: Vec<String>,
+}
+
+impl EnvResolver<'_> {
+    fn resolve(&mut self, name: &str) -> EnvResult<()> {
+        if self.resolved.contains_key(name) || self.unresolvable.contains(name) {
+            return Ok(());
+        }
+        let value = match self.env.get(name) {
+            Some(value) => value,
+            None => return Ok(()),
+        };
+        if let Some(start) = self.stack.iter().position(|n| n == name) {
+            let mut cycle = self.stack[start..].to_vec();
+            cycle.push(name.to_string());
+            return Err(EnvError::Cycle(cycle));
+        }
+
+        self.stack.push(name.to_string());
+        for reference in value.references() {
+            if reference != name || !matches!(value.kind, EnvKind::Command(_)) {
+                self.resolve(&reference)?;
+            }
+        }
+        self.stack.pop();
+
+        match &value.kind {
+            EnvKind::Value(value) => match expand_with(value, &self.resolved, self.users) {
+                Ok(expanded) => {
+                    self.resolved.insert(name.to_string(), expanded);
+                }
+                Err(err) => {
+                    match err {
+                        ExpandError::Unset(_) => debug!("Can not resolve variable {}: {}", name, err),
+                        _ => warn!("Can not resolve variable {}: {}", name, err),
+                    }
+                    self.unresolvable.insert(name.to_string());
+                }
+            },
+            EnvKind::Command(command) => match command.run(&self.resolved, self.cache) {
+                Ok(output) => {
+                    self.resolved.insert(name.to_string(), output);
+                }
+                Err(err) => {
+                    warn!("Can not resolve variable {}: {}", name, err);
+                    self.unresolvable.insert(name.to_string());
+                }
+            },
+            EnvKind::Literal(literal) => {
+                self.resolved.insert(name.to_string(), literal.clone());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Error type used by `resolve_env`
+#[derive(Error, Debug, Clone, Eq, PartialEq)]
+pub enum EnvError {
+    #[error("environment variables refer to each other: {}", .0.join(" -> "))]
+    Cycle(Vec<String>),
+}
+
+pub type EnvResult<T> = std::result::Result<T, EnvError>;
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use crate::config::{resolve_env, EnvCommand, EnvError, EnvValue};
+
+    fn env_of(vars: &[(&str, EnvValue)]) -> HashMap<String, EnvValue> {
+        vars.iter()
+            .map(|(k, v)| (k.to_string(), v.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_resolve() {
+        let env = env_of(&[
+            ("HOME", EnvValue::literal("/home/user")),
+            ("LITERAL", EnvValue::literal("$HOME")),
+            ("GOBIN", EnvValue::new("${GOPATH}/bin")),
+            ("GOPATH", EnvValue::new("~/go")),
+            ("MISSING", EnvValue::new("$UNSET/bin")),
+            ("DEPENDS_ON_MISSING", EnvValue::new("$MISSING/sub")),
+        ]);
+        let resolved = resolve_env(&env).unwrap();
+        assert_eq!(resolved["GOBIN"], "/home/user/go/bin");
+        assert_eq!(resolved["LITERAL"], "$HOME");
+        assert!(!resolved.contains_key("MISSING"));
+        assert!(!resolved.contains_key("DEPENDS_ON_MISSING"));
+    }
+
+    #[test]
+    fn test_resolve_command() {
+        let env = env_of(&[
+            ("PATH", EnvValue::literal("/usr/bin:/bin")),
+            ("SDK", EnvValue::new("/opt/sdk")),
+            ("SDK_BIN", EnvValue::command(EnvCommand::new(&["echo", "$SDK/bin"]))),
+            ("FAILING", EnvValue::command(EnvCommand::new(&["false"]))),
+        ]);
+        let resolved = resolve_env(&env).unwrap();
+        assert_eq!(resolved["SDK_BIN"], "/opt/sdk/bin");
+        assert!(!resolved.contains_key("FAILING"));
+    }
+
+    #[test]
+    fn test_cycle() {
+        let env = env_of(&[
+            ("A", EnvValue::new("$B")),
+            ("B", EnvValue::new("${C:-x}")),
+            ("C", EnvValue::new("$A")),
+        ]);
+        let err = resolve_env(&env).unwrap_err();
+        assert_eq!(err, EnvError::Cycle(vec![
+            "A".to_string(), "B".to_string(), "C".to_string(), "A".to_string(),
+        ]));
+        assert_eq!(err.to_string(), "environment variables refer to each other: A -> B -> C -> A");
+
+        let self_reference = env_of(&[("A", EnvValue::new("$A/bin"))]);
+        assert!(resolve_env(&self_reference).is_err());
+    }
+
+    #[test]
+    fn test_merge() {
+        let real = EnvValue::literal("/usr/lib/go");
+        let configured = EnvValue::new("/usr/local/go");
+        let overriding = EnvValue::new("/opt/go").overriding();
+
+        assert_eq!(configured.clone().merge(real.clone()), real);
+        assert_eq!(overriding.clone().merge(real.clone()), overriding);
+        assert_eq!(overriding.clone().merge(configured.clone().overriding()), configured.overriding());
+    }
+
+    #[test]
+    fn test_deserialize() {
+        #[derive(serde::Serialize, serde::Deserialize)]
+        struct TestType {
+            #[serde(serialize_with = "toml::ser::tables_last")]
+            env: HashMap<String, EnvValue>,
+        }
+
+        let input: TestType = toml::from_str(
+            "[env]\nGOROOT = \"/usr/local/go\"\nGOPATH = { value = \"$HOME/go\", override = true }\n\
+            GOBIN = { value = \"$GOPATH/bin\", export = true }\n\
+            PREFIX = { command = [\"brew\", \"--prefix\"], timeout_ms = 200 }\n"
+        ).unwrap();
+        assert_eq!(input.env["GOROOT"], EnvValue::new("/usr/local/go"));
+        assert_eq!(input.env["GOPATH"], EnvValue::new("$HOME/go").overriding());
+        assert_eq!(input.env["GOBIN"], EnvValue::new("$GOPATH/bin").exported());
+        assert_eq!(
+            input.env["PREFIX"],
+            EnvValue::command(EnvCommand::new(&["brew", "--prefix"]).with_timeout(Duration::from_millis(200)))
+        );
+
+        let serialized = toml::to_string(&input).unwrap();
+        let deserialized: TestType = toml::from_str(&serialized).unwrap();
+        assert_eq!(deserialized.env, input.env);
+    }
+}
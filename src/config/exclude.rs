@@ -0,0 +1,369 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fmt;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::sync::Arc;
+
+use regex::bytes::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::{MapAccess, SeqAccess, Visitor};
+use serde::de::value::MapAccessDeserializer;
+use serde::ser::SerializeSeq;
+
+use crate::config::{ConfigSource, Path, Paths, SystemUsers, UserLookup};
+
+/// Set of rules which remove unwanted entries from the generated _$PATH_ variable.
+///
+/// Entries are matched after resolving their variables.
+/// `literal` patterns are resolved the same way as paths, so `~/bin` can be excluded.
+/// `glob` patterns support `*`, `**`, `?` and `[...]`, `regex` patterns are matched
+/// against the whole resolved path.
+/// Globs match any byte, so they also work on paths which are not valid UTF-8.
+///
+/// # Examples
+///
+/// ```
+/// use pathfix::config::Exclude;
+///
+/// let exclude: Exclude = toml::from_str(r#"
+///     literal = ["."]
+///     glob = ["/mnt/c/**"]
+///     regex = ["^/opt/old-.*"]
+///     path_var_only = true
+/// "#).unwrap();
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct Exclude {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub literal: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub glob: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub regex: Vec<String>,
+    /// Only remove entries which originate from the _$PATH_ variable
+    #[serde(default)]
+    pub path_var_only: bool,
+    #[serde(skip)]
+    source: Option<Arc<ConfigSource>>,
+}
+
+impl Exclude {
+    /// Returns the source of where the rule originates from
+    pub fn source(&self) -> Option<&Arc<ConfigSource>> {
+        self.source.as_ref()
+    }
+
+    fn matcher(&self, env: &HashMap<String, OsString>, users: &dyn UserLookup) -> io::Result<ExcludeMatcher> {
+        let regex = self.glob.iter()
+            .map(|glob| glob_to_regex(glob))
+            .chain(self.regex.iter().map(|regex| format!("^(?:{})$", regex)))
+            .map(|regex| Regex::new(&regex).map_err(
+                |err| io::Error::new(io::ErrorKind::InvalidData, err)
+            ))
+            .collect::<io::Result<_>>()?;
+        Ok(ExcludeMatcher {
+            literal: self.literal.iter()
+                .filter_map(|literal| Path::from(literal.as_str()).resolve_with(env, users))
+                .map(|literal| trim_trailing_slash(literal.as_os_str().as_bytes()).to_vec())
+                .collect(),
+            regex,
+        })
+    }
+}
+
+struct ExcludeMatcher {
+    literal: Vec<Vec<u8>>,
+    regex: Vec<Regex>,
+}
+
+impl ExcludeMatcher {
+    fn matches(&self, path: &[u8]) -> bool {
+        let trimmed = trim_trailing_slash(path);
+        self.literal.iter().any(|literal| literal == trimmed)
+            || self.regex.iter().any(|regex| regex.is_match(path))
+    }
+}
+
+fn trim_trailing_slash(path: &[u8]) -> &[u8] {
+    let mut trimmed = path;
+    while let Some(rest) = trimmed.strip_suffix(b"/") {
+        trimmed = rest;
+    }
+    if trimmed.is_empty() {
+        path
+    } else {
+        trimmed
+    }
+}
+
+/// Translates a glob pattern to an anchored regular expression, which matches bytes
+pub(crate) fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("(?s-u)^");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                regex.push_str(".*");
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' => {
+                regex.push('[');
+                if chars.peek() == Some(&'!') {
+                    chars.next();
+                    regex.push('^');
+                }
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    if c == '\\' || c == '[' {
+                        regex.push('\\');
+                    }
+                    regex.push(c);
+                }
+                regex.push(']');
+            }
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+/// List of exclusion rules
+///
+/// Every configuration file can contribute an `[exclude]` table,
+/// merged configurations keep the rules of all files.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct Excludes(pub Vec<Exclude>);
+
+impl Excludes {
+    pub fn new(v: Vec<Exclude>) -> Excludes {
+        Excludes(v)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Merges two `Excludes` structures.
+    /// Rules of `other` will be inserted before `self`.
+    pub fn merge(self, other: Excludes) -> Excludes {
+        Excludes(other.0.into_iter().chain(self.0).collect())
+    }
+
+    /// Sets the source of all rules in the internal vector.
+    pub fn set_source(&mut self, source: ConfigSource) {
+        let source = Arc::new(source);
+        for exclude in self.0.iter_mut() {
+            exclude.source = Some(source.clone());
+        }
+    }
+
+    /// Removes all paths which match any rule.
+    ///
+    /// Paths are matched with their resolved value, or their raw value if they can't be resolved.
+    /// Rules which are marked as `path_var_only` only apply to paths from the _$PATH_ variable.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use pathfix::config::{Exclude, Excludes, Paths};
+    ///
+    /// let exclude: Exclude = toml::from_str(r#"glob = ["/mnt/c/*"]"#).unwrap();
+    /// let excludes = Excludes::new(vec![exclude]);
+    /// let paths = Paths::from_path("/usr/bin:/mnt/c/Windows");
+    ///
+    /// assert_eq!(excludes.filter(paths, &HashMap::new()).unwrap(), Paths::from_path("/usr/bin"));
+    /// ```
+    pub fn filter(&self, paths: Paths, env: &HashMap<String, OsString>) -> io::Result<Paths> {
+        self.filter_with(paths, env, &SystemUsers)
+    }
+
+    /// Like `filter`, but looks up the home directories of `~user` in `users`.
+    pub fn filter_with(&self, paths: Paths, env: &HashMap<String, OsString>, users: &dyn UserLookup) -> io::Result<Paths> {
+        self.partition_with(paths, env, users).map(|(kept, _)| kept)
+    }
+
+    /// Like `filter_with`, but returns the removed paths as well, both in their original order.
+    pub fn partition_with(
+        &self,
+        paths: Paths,
+        env: &HashMap<String, OsString>,
+        users: &dyn UserLookup,
+    ) -> io::Result<(Paths, Paths)> {
+        let matchers = self.0.iter()
+            .map(|exclude| exclude.matcher(env, users).map(|matcher| (exclude, matcher)))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let (kept, excluded): (Vec<Path>, Vec<Path>) = paths.into_iter().partition(|path| {
+            let from_path_var = path.source()
+                .map(|source| **source == ConfigSource::PathVar)
+                .unwrap_or(false);
+            let resolved = path.resolve_with(env, users).unwrap_or_else(|| path.path().into());
+            let rule = matchers.iter()
+                .filter(|(exclude, _)| !exclude.path_var_only || from_path_var)
+                .find(|(_, matcher)| matcher.matches(resolved.as_os_str().as_bytes()));
+            match rule.and_then(|(exclude, _)| exclude.source()) {
+                Some(source) => debug!("Excluded {} by rule from {}", resolved.display(), source),
+                None if rule.is_some() => debug!("Excluded {}", resolved.display()),
+                None => {}
+            }
+            rule.is_none()
+        });
+        Ok((Paths::new(kept), Paths::new(excluded)))
+    }
+}
+
+impl Serialize for Excludes {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where
+        S: Serializer,
+    {
+        match self.0.as_slice() {
+            [exclude] => exclude.serialize(serializer),
+            excludes => {
+                let mut serialize_seq = serializer.serialize_seq(Some(excludes.len()))?;
+                for exclude in excludes {
+                    serialize_seq.serialize_element(exclude)?;
+                }
+                serialize_seq.end()
+            }
+        }
+    }
+}
+
+struct ExcludesVisitor;
+
+impl<'de> Visitor<'de> for ExcludesVisitor {
+    type Value = Excludes;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an exclude table or an array of exclude tables")
+    }
+
+    fn visit_map<A>(self, map: A) -> Result<Excludes, A::Error> where
+        A: MapAccess<'de>, {
+        Ok(Excludes(vec![Exclude::deserialize(MapAccessDeserializer::new(map))?]))
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Excludes, A::Error> where
+        A: SeqAccess<'de>, {
+        let mut excludes = Vec::new();
+        while let Some(exclude) = seq.next_element()? {
+            excludes.push(exclude)
+        }
+        Ok(Excludes(excludes))
+    }
+}
+
+impl<'de> Deserialize<'de> for Excludes {
+    fn deserialize<D>(deserializer: D) -> Result<Excludes, D::Error> where
+        D: Deserializer<'de> {
+        deserializer.deserialize_any(ExcludesVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::ffi::{OsStr, OsString};
+    use std::os::unix::ffi::OsStrExt;
+
+    use crate::config::{ConfigSource, Exclude, Excludes, Path, Paths, PathFlags, SystemUsers};
+    use super::glob_to_regex;
+
+    fn env() -> HashMap<String, OsString> {
+        [("HOME", "/home/user")]
+            .iter()
+            .map(|(k, v)| (k.to_string(), OsString::from(v)))
+            .collect()
+    }
+
+    #[test]
+    fn test_glob_to_regex() {
+        let cases = [
+            ("/mnt/c/*", "(?s-u)^/mnt/c/[^/]*$"),
+            ("/opt/**/bin", "(?s-u)^/opt/.*/bin$"),
+            ("/opt/sdk-?", "(?s-u)^/opt/sdk\\-[^/]$"),
+            ("/opt/[!a]", "(?s-u)^/opt/[^a]$"),
+        ];
+        for (glob, regex) in &cases {
+            assert_eq!(&glob_to_regex(glob), regex);
+        }
+    }
+
+    #[test]
+    fn test_filter() {
+        let excludes = Excludes::new(vec![Exclude {
+            literal: vec![".".to_string(), "~/old/".to_string()],
+            glob: vec!["/mnt/c/**".to_string()],
+            regex: vec!["/opt/old-[a-z]+/bin".to_string()],
+            ..Default::default()
+        }]);
+        let paths = Paths::from_path(
+            "/usr/bin:.:/home/user/old:/mnt/c/Windows/System32:/opt/old-sdk/bin:/opt/old-sdk/bin/extra"
+        );
+        assert_eq!(
+            excludes.filter(paths, &env()).unwrap(),
+            Paths::from_path("/usr/bin:/opt/old-sdk/bin/extra")
+        );
+    }
+
+    #[test]
+    fn test_filter_non_utf8() {
+        let excludes = Excludes::new(vec![Exclude {
+            glob: vec!["/home/j?rg/*".to_string()],
+            ..Default::default()
+        }]);
+        let paths = Paths::from_path(OsStr::from_bytes(b"/usr/bin:/home/j\xf6rg/bin"));
+        assert_eq!(excludes.filter(paths, &env()).unwrap(), Paths::from_path("/usr/bin"));
+    }
+
+    #[test]
+    fn test_filter_path_var_only() {
+        let excludes = Excludes::new(vec![Exclude {
+            literal: vec!["/usr/games".to_string()],
+            path_var_only: true,
+            ..Default::default()
+        }]);
+        let mut included = Paths::from(vec!["/usr/games"]);
+        included.set_source(ConfigSource::Included);
+        let paths = Paths::from_path("/usr/games").merge(included.clone());
+        assert_eq!(excludes.filter(paths.clone(), &env()).unwrap(), included);
+
+        let (kept, excluded) = excludes.partition_with(paths, &env(), &SystemUsers).unwrap();
+        assert_eq!(kept, included);
+        assert_eq!(excluded, Paths::from_path("/usr/games"));
+    }
+
+    #[test]
+    fn test_invalid_regex() {
+        let excludes = Excludes::new(vec![Exclude {
+            regex: vec!["(".to_string()],
+            ..Default::default()
+        }]);
+        let paths = Paths::new(vec![Path::new("/usr/bin", PathFlags::new())]);
+        assert!(excludes.filter(paths, &env()).is_err());
+    }
+
+    #[test]
+    fn test_deserialize() {
+        #[derive(serde::Deserialize)]
+        struct TestType {
+            exclude: Excludes,
+        }
+
+        let single: TestType = toml::from_str("[exclude]\nliteral = [\".\"]\n").unwrap();
+        assert_eq!(single.exclude.0.len(), 1);
+
+        let multiple: TestType = toml::from_str(
+            "[[exclude]]\nliteral = [\".\"]\n[[exclude]]\nglob = [\"/mnt/*\"]\npath_var_only = true\n"
+        ).unwrap();
+        assert_eq!(multiple.exclude.0.len(), 2);
+        assert!(multiple.exclude.0[1].path_var_only);
+    }
+}
@@ -0,0 +1,329 @@
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::iter::Peekable;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::slice::Iter;
+
+use thiserror::Error;
+
+use crate::config::{SystemUsers, UserLookup};
+
+/// Expands variables and a leading tilde in `s` the way a POSIX shell does.
+///
+/// Supported are `~`, `~user`, `$VAR`, `${VAR}`, `${VAR-default}`, `${VAR:-default}`,
+/// `${VAR?message}`, `${VAR:?message}` and `$$` for a literal `$`.
+/// Variables can appear anywhere in the string, defaults are expanded themselves.
+/// Neither `s` nor the values of variables have to be valid UTF-8.
+///
+/// # Examples
+///
+/// ```
+/// use std::collections::HashMap;
+/// use pathfix::config::expand;
+///
+/// let mut env = HashMap::new();
+/// env.insert("USER".to_string(), "alice".into());
+///
+/// assert_eq!(expand("/opt/$USER-bin", &env).unwrap(), "/opt/alice-bin");
+/// assert_eq!(expand("${GOPATH:-/opt/go}/bin", &env).unwrap(), "/opt/go/bin");
+/// assert_eq!(expand("/cost/$$5", &env).unwrap(), "/cost/$5");
+/// assert!(expand("$GOPATH/bin", &env).is_err());
+/// ```
+pub fn expand<S: AsRef<OsStr>>(s: S, env: &HashMap<String, OsString>) -> ExpandResult<OsString> {
+    expand_with(s, env, &SystemUsers)
+}
+
+/// Like `expand`, but looks up the home directories of `~user` in `users`.
+///
+/// # Examples
+///
+/// ```
+/// use std::collections::HashMap;
+/// use pathfix::config::{expand_with, PasswdFiles};
+///
+/// let users = PasswdFiles::parse("alice:x:1000:1000::/srv/alice:/bin/sh\n", "");
+///
+/// assert_eq!(expand_with("~alice/bin", &HashMap::new(), &users).unwrap(), "/srv/alice/bin");
+/// assert!(expand_with("~bob/bin", &HashMap::new(), &users).is_err());
+/// ```
+pub fn expand_with<S: AsRef<OsStr>>(
+    s: S,
+    env: &HashMap<String, OsString>,
+    users: &dyn UserLookup,
+) -> ExpandResult<OsString> {
+    let (home, rest) = expand_tilde(s.as_ref().as_bytes(), env, users)?;
+    let mut bytes = rest.iter().peekable();
+    let mut expanded = home.unwrap_or_default();
+    expand_bytes(&mut bytes, env, &mut expanded)?;
+    Ok(OsString::from_vec(expanded))
+}
+
+/// Returns the names of all variables `s` refers to, in order of their appearance.
+///
+/// A leading `~` refers to `HOME`. Variables in defaults are included as well.
+///
+/// # Examples
+///
+/// ```
+/// use pathfix::config::references;
+///
+/// assert_eq!(references("~/go:${GOROOT:-$HOME/sdk}"), vec!["HOME", "GOROOT", "HOME"]);
+/// ```
+pub fn references(s: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    if s == "~" || s.starts_with("~/") {
+        names.push("HOME".to_string());
+    }
+    let mut bytes = s.as_bytes().iter().peekable();
+    while let Some(&b) = bytes.next() {
+        if b != b'$' {
+            continue;
+        }
+        if bytes.peek() == Some(&&b'$') {
+            bytes.next();
+            continue;
+        }
+        if bytes.peek() == Some(&&b'{') {
+            bytes.next();
+        }
+        let name = take_name(&mut bytes);
+        if !name.is_empty() {
+            names.push(name);
+        }
+    }
+    names
+}
+
+/// Replaces a tilde prefix with the home directory of the current or the given user.
+fn expand_tilde<'a>(
+    s: &'a [u8],
+    env: &HashMap<String, OsString>,
+    users: &dyn UserLookup,
+) -> ExpandResult<(Option<Vec<u8>>, &'a [u8])> {
+    let rest = match s.strip_prefix(b"~") {
+        Some(rest) => rest,
+        None => return Ok((None, s)),
+    };
+    let (user, rest) = match rest.iter().position(|&b| b == b'/') {
+        Some(index) => rest.split_at(index),
+        None => (rest, &[][..]),
+    };
+    let home = if user.is_empty() {
+        env.get("HOME")
+            .map(|home| home.as_bytes().to_vec())
+            .ok_or_else(|| ExpandError::Unset("HOME".to_string()))?
+    } else {
+        // user names which are not valid UTF-8 can not be looked up
+        let user = String::from_utf8_lossy(user);
+        users.user_by_name(&user)
+            .ok()
+            .flatten()
+            .ok_or_else(|| ExpandError::UnknownUser(user.into_owned()))?
+            .home
+            .into_os_string()
+            .into_vec()
+    };
+    Ok((Some(home), rest))
+}
+
+fn expand_bytes(
+    bytes: &mut Peekable<Iter<u8>>,
+    env: &HashMap<String, OsString>,
+    expanded: &mut Vec<u8>,
+) -> ExpandResult<()> {
+    while let Some(&b) = bytes.next() {
+        if b != b'$' {
+            expanded.push(b);
+            continue;
+        }
+        match bytes.peek() {
+            Some(b'$') => {
+                bytes.next();
+                expanded.push(b'$');
+            }
+            Some(b'{') => {
+                bytes.next();
+                expanded.extend(expand_braces(bytes, env)?);
+            }
+            Some(&&b) if is_name_start(b) => {
+                let name = take_name(bytes);
+                expanded.extend(lookup(&name, env)?);
+            }
+            _ => expanded.push(b'$'),
+        }
+    }
+    Ok(())
+}
+
+/// Expands the contents of `${...}`, the opening brace is already consumed.
+fn expand_braces(bytes: &mut Peekable<Iter<u8>>, env: &HashMap<String, OsString>) -> ExpandResult<Vec<u8>> {
+    let name = take_name(bytes);
+    if name.is_empty() {
+        return Err(ExpandError::Syntax("missing variable name after '${'".to_string()));
+    }
+    let colon = bytes.peek() == Some(&&b':');
+    if colon {
+        bytes.next();
+    }
+    let operator = match bytes.next() {
+        Some(b'}') if !colon => return lookup(&name, env).map(<[u8]>::to_vec),
+        Some(&operator @ b'-') | Some(&operator @ b'?') => operator,
+        Some(&b) => return Err(ExpandError::Syntax(
+            format!("unexpected '{}' in ${{{}}}", String::from_utf8_lossy(&[b]), name)
+        )),
+        None => return Err(ExpandError::Syntax(format!("missing '}}' after ${{{}", name))),
+    };
+    let word = take_word(bytes)
+        .ok_or_else(|| ExpandError::Syntax(format!("missing '}}' after ${{{}", name)))?;
+
+    let value = env.get(&name).filter(|value| !colon || !value.is_empty());
+    match (value, operator) {
+        (Some(value), _) => Ok(value.as_bytes().to_vec()),
+        (None, b'-') => {
+            let mut expanded = Vec::new();
+            expand_bytes(&mut word.iter().peekable(), env, &mut expanded)?;
+            Ok(expanded)
+        }
+        (None, _) if word.is_empty() => Err(ExpandError::Unset(name)),
+        (None, _) => Err(ExpandError::Message {
+            name,
+            message: String::from_utf8_lossy(&word).into_owned(),
+        }),
+    }
+}
+
+/// Takes the word up to the matching closing brace and consumes the brace.
+fn take_word(bytes: &mut Peekable<Iter<u8>>) -> Option<Vec<u8>> {
+    let mut word = Vec::new();
+    let mut depth = 0;
+    for &b in bytes.by_ref() {
+        match b {
+            b'{' => depth += 1,
+            b'}' if depth == 0 => return Some(word),
+            b'}' => depth -= 1,
+            _ => {}
+        }
+        word.push(b);
+    }
+    None
+}
+
+fn take_name(bytes: &mut Peekable<Iter<u8>>) -> String {
+    let mut name = String::new();
+    while let Some(&&b) = bytes.peek() {
+        if !(is_name_start(b) || b.is_ascii_digit()) {
+            break;
+        }
+        name.push(char::from(b));
+        bytes.next();
+    }
+    name
+}
+
+fn is_name_start(b: u8) -> bool {
+    b.is_ascii_alphabetic() || b == b'_'
+}
+
+fn lookup<'a>(name: &str, env: &'a HashMap<String, OsString>) -> ExpandResult<&'a [u8]> {
+    env.get(name)
+        .map(|value| value.as_bytes())
+        .ok_or_else(|| ExpandError::Unset(name.to_string()))
+}
+
+/// Error type used by `expand`
+#[derive(Error, Debug, Clone, Eq, PartialEq)]
+pub enum ExpandError {
+    #[error("variable {0} is not set")]
+    Unset(String),
+    #[error("{name}: {message}")]
+    Message { name: String, message: String },
+    #[error("user {0} does not exist")]
+    UnknownUser(String),
+    #[error("invalid variable expansion: {0}")]
+    Syntax(String),
+}
+
+pub type ExpandResult<T> = std::result::Result<T, ExpandError>;
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::ffi::{OsStr, OsString};
+    use std::os::unix::ffi::OsStrExt;
+
+    use users::os::unix::UserExt;
+
+    use super::{expand, ExpandError};
+
+    fn env() -> HashMap<String, OsString> {
+        [("HOME", "/home/user"), ("USER", "alice"), ("EMPTY", "")]
+            .iter()
+            .map(|(k, v)| (k.to_string(), OsString::from(v)))
+            .collect()
+    }
+
+    #[test]
+    fn test_expand() {
+        let cases = [
+            ("/usr/bin", "/usr/bin"),
+            ("~", "/home/user"),
+            ("~/bin", "/home/user/bin"),
+            ("/foo/~/bar", "/foo/~/bar"),
+            ("$HOME/bin", "/home/user/bin"),
+            ("${HOME}/bin", "/home/user/bin"),
+            ("${HOME}_tools", "/home/user_tools"),
+            ("/opt/$USER-bin", "/opt/alice-bin"),
+            ("/opt/${UNSET:-default}", "/opt/default"),
+            ("/opt/${EMPTY:-default}", "/opt/default"),
+            ("/opt/${EMPTY-default}", "/opt/"),
+            ("/opt/${UNSET:-${USER}}/bin", "/opt/alice/bin"),
+            ("/opt/${UNSET:-{x}}", "/opt/{x}"),
+            ("/opt/$$USER", "/opt/$USER"),
+            ("/opt/$", "/opt/$"),
+            ("/opt/$-", "/opt/$-"),
+        ];
+        for (s, wanted) in &cases {
+            assert_eq!(&expand(s, &env()).unwrap(), wanted, "expanding {}", s);
+        }
+    }
+
+    #[test]
+    fn test_expand_non_utf8() {
+        let mut env = env();
+        env.insert("HOME".to_string(), OsStr::from_bytes(b"/home/j\xf6rg").to_owned());
+        assert_eq!(expand("~/bin", &env).unwrap(), OsStr::from_bytes(b"/home/j\xf6rg/bin"));
+        assert_eq!(
+            expand(OsStr::from_bytes(b"/opt/\xe9/$USER"), &env).unwrap(),
+            OsStr::from_bytes(b"/opt/\xe9/alice")
+        );
+    }
+
+    #[test]
+    fn test_expand_errors() {
+        let cases = [
+            ("$UNSET/bin", ExpandError::Unset("UNSET".to_string())),
+            ("${UNSET}/bin", ExpandError::Unset("UNSET".to_string())),
+            ("${UNSET:?}/bin", ExpandError::Unset("UNSET".to_string())),
+            (
+                "${EMPTY:?must be set}",
+                ExpandError::Message { name: "EMPTY".to_string(), message: "must be set".to_string() },
+            ),
+            ("~thisuserdoesnotexist/bin", ExpandError::UnknownUser("thisuserdoesnotexist".to_string())),
+            ("${HOME", ExpandError::Syntax("missing '}' after ${HOME".to_string())),
+            ("${}", ExpandError::Syntax("missing variable name after '${'".to_string())),
+            ("${HOME+x}", ExpandError::Syntax("unexpected '+' in ${HOME}".to_string())),
+        ];
+        for (s, wanted) in &cases {
+            assert_eq!(&expand(s, &env()).unwrap_err(), wanted, "expanding {}", s);
+        }
+    }
+
+    #[test]
+    fn test_expand_user() {
+        if let Some(root) = users::get_user_by_uid(0) {
+            let wanted = root.home_dir().join("bin");
+            let name = root.name().to_string_lossy().into_owned();
+            assert_eq!(expand(format!("~{}/bin", name), &env()).unwrap(), wanted.into_os_string());
+        }
+    }
+}
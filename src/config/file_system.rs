@@ -0,0 +1,259 @@
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// Maximum number of symbolic links followed while resolving a path
+const MAX_SYMLINKS: usize = 40;
+
+/// File system from which configurations are read and on which paths are checked
+pub trait FileSystem: Debug + Send + Sync {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// Returns if `path` is a directory, following symbolic links
+    fn is_dir(&self, path: &Path) -> bool;
+
+    /// Returns if anything exists at `path`, following symbolic links
+    fn exists(&self, path: &Path) -> bool;
+
+    /// Returns the paths of all entries of the directory `path`
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
+
+    /// Returns the absolute path of an existing `path` without symbolic links
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf>;
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        String::from_utf8(self.read(path)?)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))
+    }
+}
+
+/// File system of the running system
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct RealFileSystem;
+
+impl FileSystem for RealFileSystem {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        fs::metadata(path)
+            .map(|metadata| metadata.is_dir())
+            .unwrap_or(false)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect()
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::canonicalize(path)
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Node {
+    Dir,
+    File(Vec<u8>),
+    Symlink(PathBuf),
+}
+
+/// File system which only exists in memory, e.g. to describe another machine in tests
+///
+/// Relative paths are relative to `/`.
+///
+/// # Examples
+///
+/// ```
+/// use std::path::Path;
+/// use pathfix::config::{FileSystem, MemoryFileSystem};
+///
+/// let fs = MemoryFileSystem::new()
+///     .with_dir("/usr/bin")
+///     .with_file("/etc/pathfix.toml", "base = true\n")
+///     .with_symlink("/bin", "usr/bin");
+///
+/// assert!(fs.is_dir(Path::new("/bin")));
+/// assert!(!fs.is_dir(Path::new("/etc/pathfix.toml")));
+/// assert_eq!(fs.read_to_string(Path::new("/etc/pathfix.toml")).unwrap(), "base = true\n");
+/// ```
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct MemoryFileSystem {
+    nodes: BTreeMap<PathBuf, Node>,
+}
+
+impl MemoryFileSystem {
+    /// Creates a file system which only contains `/`
+    pub fn new() -> MemoryFileSystem {
+        let mut nodes = BTreeMap::new();
+        nodes.insert(PathBuf::from("/"), Node::Dir);
+        MemoryFileSystem { nodes }
+    }
+
+    /// Adds a directory and its parents
+    pub fn with_dir<P: AsRef<Path>>(self, path: P) -> MemoryFileSystem {
+        self.with_node(path.as_ref(), Node::Dir)
+    }
+
+    /// Adds a file and its parent directories
+    pub fn with_file<P: AsRef<Path>, C: Into<Vec<u8>>>(self, path: P, contents: C) -> MemoryFileSystem {
+        self.with_node(path.as_ref(), Node::File(contents.into()))
+    }
+
+    /// Adds a symbolic link to `target`, which is relative to the parent of `path` if it is relative
+    pub fn with_symlink<P: AsRef<Path>, T: Into<PathBuf>>(self, path: P, target: T) -> MemoryFileSystem {
+        self.with_node(path.as_ref(), Node::Symlink(target.into()))
+    }
+
+    fn with_node(mut self, path: &Path, node: Node) -> MemoryFileSystem {
+        let path = normalize(Path::new("/"), path);
+        for parent in path.ancestors().skip(1) {
+            self.nodes.entry(parent.to_path_buf()).or_insert(Node::Dir);
+        }
+        self.nodes.insert(path, node);
+        self
+    }
+
+    /// Returns the node at `path` after following all symbolic links
+    fn node(&self, path: &Path) -> io::Result<(PathBuf, &Node)> {
+        let path = self.canonicalize(path)?;
+        match self.nodes.get(&path) {
+            Some(node) => Ok((path, node)),
+            None => Err(not_found(&path)),
+        }
+    }
+}
+
+impl Default for MemoryFileSystem {
+    fn default() -> Self {
+        MemoryFileSystem::new()
+    }
+}
+
+impl FileSystem for MemoryFileSystem {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        match self.node(path)? {
+            (_, Node::File(contents)) => Ok(contents.clone()),
+            _ => Err(io::Error::other(format!("{} is a directory", path.display()))),
+        }
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        matches!(self.node(path), Ok((_, Node::Dir)))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.node(path).is_ok()
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let dir = match self.node(path)? {
+            (dir, Node::Dir) => dir,
+            _ => return Err(io::Error::other(format!("{} is not a directory", path.display()))),
+        };
+        // entries are listed below the path which was asked for, like `std::fs::read_dir`
+        Ok(self.nodes.keys()
+            .filter(|entry| entry.parent() == Some(dir.as_path()))
+            .filter_map(|entry| entry.file_name())
+            .map(|name| path.join(name))
+            .collect())
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        let mut resolved = PathBuf::from("/");
+        let mut remaining: Vec<PathBuf> = vec![normalize(Path::new("/"), path)];
+        let mut symlinks = 0;
+        while let Some(next) = remaining.pop() {
+            let mut components = next.components();
+            let component = match components.next() {
+                Some(component) => component,
+                None => continue,
+            };
+            let rest = components.as_path();
+            if !rest.as_os_str().is_empty() {
+                remaining.push(rest.to_path_buf());
+            }
+            match component {
+                Component::RootDir => resolved = PathBuf::from("/"),
+                Component::ParentDir => {
+                    resolved.pop();
+                }
+                Component::CurDir | Component::Prefix(_) => {}
+                Component::Normal(name) => {
+                    let candidate = resolved.join(name);
+                    match self.nodes.get(&candidate) {
+                        Some(Node::Symlink(target)) => {
+                            symlinks += 1;
+                            if symlinks > MAX_SYMLINKS {
+                                return Err(io::Error::other(
+                                    format!("too many levels of symbolic links: {}", path.display()),
+                                ));
+                            }
+                            remaining.push(target.clone());
+                        }
+                        Some(_) => resolved = candidate,
+                        None => return Err(not_found(path)),
+                    }
+                }
+            }
+        }
+        Ok(resolved)
+    }
+}
+
+/// Makes `path` absolute below `base` without resolving `..`, which might follow a link
+fn normalize(base: &Path, path: &Path) -> PathBuf {
+    let mut normalized = base.to_path_buf();
+    for component in path.components() {
+        match component {
+            Component::RootDir => normalized = PathBuf::from("/"),
+            Component::CurDir | Component::Prefix(_) => {}
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("{} does not exist", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::config::{FileSystem, MemoryFileSystem};
+
+    #[test]
+    fn test_memory_file_system() {
+        let fs = MemoryFileSystem::new()
+            .with_dir("/usr/local/bin")
+            .with_file("/usr/bin/ls", "")
+            .with_symlink("/bin", "usr/bin")
+            .with_symlink("/opt/tools", "/usr/local/../bin")
+            .with_symlink("/loop", "/loop");
+
+        assert!(fs.is_dir(Path::new("/usr/local")));
+        assert!(fs.is_dir(Path::new("/bin")));
+        assert!(fs.exists(Path::new("/bin/ls")));
+        assert!(!fs.is_dir(Path::new("/bin/ls")));
+        assert!(!fs.exists(Path::new("/sbin")));
+        assert_eq!(fs.canonicalize(Path::new("/opt/tools/ls")).unwrap(), Path::new("/usr/bin/ls"));
+        assert_eq!(fs.canonicalize(Path::new("usr/./local/")).unwrap(), Path::new("/usr/local"));
+        assert!(fs.canonicalize(Path::new("/loop")).is_err());
+        assert_eq!(
+            fs.read_dir(Path::new("/usr")).unwrap(),
+            vec![Path::new("/usr/bin"), Path::new("/usr/local")],
+        );
+        assert_eq!(fs.read_dir(Path::new("/bin")).unwrap(), vec![Path::new("/bin/ls")]);
+        assert!(fs.read(Path::new("/usr")).is_err());
+    }
+}
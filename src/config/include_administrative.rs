@@ -1,20 +1,178 @@
+use std::collections::BTreeMap;
+use std::fmt;
 use std::io;
-use std::string::ToString;
+use std::path::{Path, PathBuf};
 
-use serde::{Deserialize, Serialize};
-use users::Group;
+use serde::de::{self, IgnoredAny, MapAccess, Visitor};
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::config::{
+    FileSystem, GroupEntry, PasswdFiles, RealFileSystem, SystemUsers, UserEntry, UserLookup,
+};
 
 /// Policy under which the user should be considered an administrator.
-#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
-#[serde(rename_all = "kebab-case")]
+///
+/// Policies can be combined with `any`, `all` and `not`.
+///
+/// # Examples
+///
+/// ```
+/// use pathfix::config::{Config, IncludeAdministrative};
+///
+/// let config: Config = toml::from_str(
+///     r#"include_administrative = { any = [ { groups = ["wheel"] }, { users = ["deploy"] } ] }"#
+/// ).unwrap();
+///
+/// assert_eq!(config.include_administrative, Some(IncludeAdministrative::Any(vec![
+///     IncludeAdministrative::Groups(vec!["wheel".to_string()]),
+///     IncludeAdministrative::Users(vec!["deploy".to_string()]),
+/// ])));
+/// ```
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub enum IncludeAdministrative {
     Always,
     RootOnly,
     Users(Vec<String>),
     Groups(Vec<String>),
+    Uids(Vec<u32>),
+    Gids(Vec<u32>),
+    UidRange(UidRange),
+    /// Users which can run all commands as root without a password according to a sudoers file
+    SudoNopasswd(PathBuf),
+    Any(Vec<IncludeAdministrative>),
+    All(Vec<IncludeAdministrative>),
+    Not(Box<IncludeAdministrative>),
     Never,
 }
 
+const UNIT_POLICIES: &[&str] = &["always", "root-only", "never"];
+
+const POLICIES: &[&str] = &[
+    "always", "root-only", "users", "groups", "uids", "gids", "uid-range", "sudo-nopasswd", "any", "all", "not", "never",
+];
+
+// toml can not serialize newtype variants, so they are written as a table with a single key
+impl Serialize for IncludeAdministrative {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where
+        S: Serializer,
+    {
+        match self {
+            IncludeAdministrative::Always => serializer.serialize_str("always"),
+            IncludeAdministrative::RootOnly => serializer.serialize_str("root-only"),
+            IncludeAdministrative::Never => serializer.serialize_str("never"),
+            policy => AsTable(policy).serialize(serializer),
+        }
+    }
+}
+
+/// Writes every policy as a table, including `{ always = {} }`.
+///
+/// toml can not write arrays which mix strings and tables, so this is used for `any` and `all`.
+struct AsTable<'a>(&'a IncludeAdministrative);
+
+impl Serialize for AsTable<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where
+        S: Serializer,
+    {
+        fn table<S: Serializer, T: Serialize + ?Sized>(serializer: S, key: &str, value: &T) -> Result<S::Ok, S::Error> {
+            let mut serialize_map = serializer.serialize_map(Some(1))?;
+            serialize_map.serialize_entry(key, value)?;
+            serialize_map.end()
+        }
+        fn tables<S: Serializer>(serializer: S, key: &str, policies: &[IncludeAdministrative]) -> Result<S::Ok, S::Error> {
+            let policies: Vec<AsTable> = policies.iter().map(AsTable).collect();
+            table(serializer, key, &policies)
+        }
+
+        let empty = BTreeMap::<String, String>::new();
+        match self.0 {
+            IncludeAdministrative::Always => table(serializer, "always", &empty),
+            IncludeAdministrative::RootOnly => table(serializer, "root-only", &empty),
+            IncludeAdministrative::Never => table(serializer, "never", &empty),
+            IncludeAdministrative::Users(users) => table(serializer, "users", users),
+            IncludeAdministrative::Groups(groups) => table(serializer, "groups", groups),
+            IncludeAdministrative::Uids(uids) => table(serializer, "uids", uids),
+            IncludeAdministrative::Gids(gids) => table(serializer, "gids", gids),
+            IncludeAdministrative::UidRange(range) => table(serializer, "uid-range", range),
+            IncludeAdministrative::SudoNopasswd(sudoers) => table(serializer, "sudo-nopasswd", sudoers),
+            IncludeAdministrative::Any(policies) => tables(serializer, "any", policies),
+            IncludeAdministrative::All(policies) => tables(serializer, "all", policies),
+            IncludeAdministrative::Not(policy) => table(serializer, "not", policy),
+        }
+    }
+}
+
+// toml can only read enums from inline tables, but nested policies are written as `[[x.any]]`
+impl<'de> Deserialize<'de> for IncludeAdministrative {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(PolicyVisitor)
+    }
+}
+
+struct PolicyVisitor;
+
+impl<'de> Visitor<'de> for PolicyVisitor {
+    type Value = IncludeAdministrative;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a policy name or a table with a single policy")
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E> where
+        E: de::Error,
+    {
+        match v {
+            "always" => Ok(IncludeAdministrative::Always),
+            "root-only" => Ok(IncludeAdministrative::RootOnly),
+            "never" => Ok(IncludeAdministrative::Never),
+            _ => Err(de::Error::unknown_variant(v, UNIT_POLICIES)),
+        }
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error> where
+        A: MapAccess<'de>,
+    {
+        let key: String = map.next_key()?.ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let policy = match key.as_str() {
+            "always" => map.next_value::<IgnoredAny>().map(|_| IncludeAdministrative::Always)?,
+            "root-only" => map.next_value::<IgnoredAny>().map(|_| IncludeAdministrative::RootOnly)?,
+            "never" => map.next_value::<IgnoredAny>().map(|_| IncludeAdministrative::Never)?,
+            "users" => IncludeAdministrative::Users(map.next_value()?),
+            "groups" => IncludeAdministrative::Groups(map.next_value()?),
+            "uids" => IncludeAdministrative::Uids(map.next_value()?),
+            "gids" => IncludeAdministrative::Gids(map.next_value()?),
+            "uid-range" => IncludeAdministrative::UidRange(map.next_value()?),
+            "sudo-nopasswd" => IncludeAdministrative::SudoNopasswd(map.next_value()?),
+            "any" => IncludeAdministrative::Any(map.next_value()?),
+            "all" => IncludeAdministrative::All(map.next_value()?),
+            "not" => IncludeAdministrative::Not(map.next_value()?),
+            _ => return Err(de::Error::unknown_variant(&key, POLICIES)),
+        };
+        if map.next_key::<IgnoredAny>()?.is_some() {
+            return Err(de::Error::invalid_length(2, &self));
+        }
+        Ok(policy)
+    }
+}
+
+/// Inclusive range of user ids, e.g. `{ max = 999 }` for system users
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct UidRange {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max: Option<u32>,
+}
+
+impl UidRange {
+    pub fn contains(&self, uid: u32) -> bool {
+        self.min.is_none_or(|min| uid >= min) && self.max.is_none_or(|max| uid <= max)
+    }
+}
+
 impl IncludeAdministrative {
     /// `check_current_user` will check if the running users qualifies as an administrator.
     ///
@@ -34,31 +192,369 @@ impl IncludeAdministrative {
     /// assert_eq!(IncludeAdministrative::RootOnly.check_current_user().unwrap(), users::get_current_uid() == 0);
     /// ```
     pub fn check_current_user(&self) -> io::Result<bool> {
-        Ok(match self {
-            IncludeAdministrative::Always => true,
-            IncludeAdministrative::RootOnly => users::get_current_uid() == 0,
-            IncludeAdministrative::Users(users) => users.contains(
-                &users::get_current_username()
-                    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "could not get current user name"))?
-                    .into_string()
-                    .ok().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "usernam is not valid utf8"))?
-            ),
-            // TODO add test for Group check
-            IncludeAdministrative::Groups(groups) => {
-                for group in users::group_access_list()?
-                    .iter()
-                    .map(Group::name)
-                    .filter_map(|g| g.to_str())
-                    {
-                        if groups.contains(&group.to_string()) {
-                            return Ok(true)
-                        }
+        self.check_current_user_with(&SystemUsers)
+    }
+
+    /// Like `check_current_user`, but looks up the user and its groups in `lookup`.
+    pub fn check_current_user_with(&self, lookup: &dyn UserLookup) -> io::Result<bool> {
+        self.check_with(None, lookup, &RealFileSystem)
+    }
+
+    /// Checks if the user `name` qualifies as an administrator, like `check_current_user`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use pathfix::config::IncludeAdministrative;
+    ///
+    /// assert_eq!(IncludeAdministrative::RootOnly.check_user("root").unwrap(), true);
+    /// assert_eq!(IncludeAdministrative::Users(vec!["alice".to_string()]).check_user("alice").unwrap(), true);
+    /// ```
+    pub fn check_user(&self, name: &str) -> io::Result<bool> {
+        self.check_user_with(name, &SystemUsers)
+    }
+
+    /// Like `check_user`, but looks up the user and its groups in `lookup`.
+    pub fn check_user_with(&self, name: &str, lookup: &dyn UserLookup) -> io::Result<bool> {
+        self.check_with(Some(name), lookup, &RealFileSystem)
+    }
+
+    /// Checks the user `name`, or the current user if it is `None`, with users and groups
+    /// from `lookup` and sudoers files from `file_system`.
+    pub fn check_with(&self, name: Option<&str>, lookup: &dyn UserLookup, file_system: &dyn FileSystem) -> io::Result<bool> {
+        self.check_account(name, lookup, None, file_system)
+    }
+
+    /// Like `check_current_user`, but looks up the name and the groups of the current user
+    /// in `etc/passwd` and `etc/group` below `root`, e.g. of a container image.
+    pub fn check_current_user_in<P: AsRef<Path>>(&self, root: P) -> io::Result<bool> {
+        self.check_in_file_system(None, root.as_ref(), &RealFileSystem)
+    }
+
+    /// Like `check_user`, but looks up the user `name` and its groups below `root`.
+    pub fn check_user_in<P: AsRef<Path>>(&self, name: &str, root: P) -> io::Result<bool> {
+        self.check_in_file_system(Some(name), root.as_ref(), &RealFileSystem)
+    }
+
+    /// Checks the user `name`, or the current user if it is `None`, with users, groups
+    /// and sudoers files below `root` in `file_system`.
+    pub fn check_in_file_system(&self, name: Option<&str>, root: &Path, file_system: &dyn FileSystem) -> io::Result<bool> {
+        if !self.depends_on_user() {
+            return self.check(None, Some(root), file_system);
+        }
+        let lookup = PasswdFiles::in_root_with(root, file_system)?;
+        self.check_account(name, &lookup, Some(root), file_system)
+    }
+
+    /// Looks up the user `name`, or the current user, and checks it with files below `root`
+    fn check_account(
+        &self,
+        name: Option<&str>,
+        lookup: &dyn UserLookup,
+        root: Option<&Path>,
+        file_system: &dyn FileSystem,
+    ) -> io::Result<bool> {
+        if !self.depends_on_user() {
+            return self.check(None, root, file_system);
+        }
+        let account = match name {
+            Some(name) => Account::named(name, lookup)?,
+            None => Account::current(lookup)?,
+        };
+        self.check(Some(&account), root, file_system)
+    }
+
+    /// Returns if the policy reads sudoers files, whose includes are only known once they are read
+    pub fn reads_sudoers(&self) -> bool {
+        match self {
+            IncludeAdministrative::SudoNopasswd(_) => true,
+            IncludeAdministrative::Any(policies) | IncludeAdministrative::All(policies) => {
+                policies.iter().any(IncludeAdministrative::reads_sudoers)
+            }
+            IncludeAdministrative::Not(policy) => policy.reads_sudoers(),
+            _ => false,
+        }
+    }
+
+    /// Returns if the policy has to look up the user
+    fn depends_on_user(&self) -> bool {
+        match self {
+            IncludeAdministrative::Always | IncludeAdministrative::Never => false,
+            IncludeAdministrative::Any(policies) | IncludeAdministrative::All(policies) => {
+                policies.iter().any(IncludeAdministrative::depends_on_user)
+            }
+            IncludeAdministrative::Not(policy) => policy.depends_on_user(),
+            _ => true,
+        }
+    }
+
+    /// Evaluates the policy for `account`, which is only `None` if the policy does not depend on it.
+    ///
+    /// Files are read from `file_system` below `root`, if it is set.
+    fn check(&self, account: Option<&Account>, root: Option<&Path>, file_system: &dyn FileSystem) -> io::Result<bool> {
+        let account = match (self, account) {
+            (IncludeAdministrative::Always, _) => return Ok(true),
+            (IncludeAdministrative::Never, _) => return Ok(false),
+            (IncludeAdministrative::Any(policies), _) => {
+                for policy in policies {
+                    if policy.check(account, root, file_system)? {
+                        return Ok(true);
+                    }
+                }
+                return Ok(false);
+            }
+            (IncludeAdministrative::All(policies), _) => {
+                for policy in policies {
+                    if !policy.check(account, root, file_system)? {
+                        return Ok(false);
                     }
-                false
+                }
+                return Ok(true);
+            }
+            (IncludeAdministrative::Not(policy), _) => return policy.check(account, root, file_system).map(|admin| !admin),
+            (_, None) => return Ok(false),
+            (_, Some(account)) => account,
+        };
+        Ok(match self {
+            IncludeAdministrative::RootOnly => account.uid == Some(0),
+            IncludeAdministrative::Users(users) => account.name.as_ref().is_some_and(|name| users.contains(name)),
+            // groups can be given by name or by numeric id
+            IncludeAdministrative::Groups(groups) => account.groups()?.iter().any(|group| {
+                groups.iter().any(|g| *g == group.name || g.parse() == Ok(group.gid))
+            }),
+            IncludeAdministrative::Uids(uids) => account.uid.is_some_and(|uid| uids.contains(&uid)),
+            IncludeAdministrative::Gids(gids) => account.groups()?
+                .iter()
+                .any(|group| gids.contains(&group.gid)),
+            IncludeAdministrative::UidRange(range) => account.uid.is_some_and(|uid| range.contains(uid)),
+            IncludeAdministrative::SudoNopasswd(path) => {
+                let path = match root {
+                    Some(root) => root.join(path.strip_prefix("/").unwrap_or(path)),
+                    None => path.clone(),
+                };
+                sudo_nopasswd(&path, account, root, file_system, 0)?
             }
-            IncludeAdministrative::Never => false,
+            _ => unreachable!("policies without a user are evaluated above"),
+        })
+    }
+}
+
+/// User which is checked against a policy
+struct Account<'a> {
+    /// Name of the user, which is known for users who do not exist
+    name: Option<String>,
+    uid: Option<u32>,
+    user: Option<UserEntry>,
+    lookup: &'a dyn UserLookup,
+}
+
+impl<'a> Account<'a> {
+    /// The user running pathfix, who might not have a passwd entry
+    fn current(lookup: &'a dyn UserLookup) -> io::Result<Account<'a>> {
+        let uid = lookup.current_uid();
+        let user = lookup.user_by_uid(uid)?;
+        Ok(Account {
+            name: user.as_ref().map(|user| user.name.clone()),
+            uid: Some(uid),
+            user,
+            lookup,
         })
     }
+
+    fn named(name: &str, lookup: &'a dyn UserLookup) -> io::Result<Account<'a>> {
+        let user = lookup.user_by_name(name)?;
+        Ok(Account {
+            name: Some(name.to_string()),
+            uid: user.as_ref().map(|user| user.uid),
+            user,
+            lookup,
+        })
+    }
+
+    /// Returns the groups of the user, including the primary group
+    fn groups(&self) -> io::Result<Vec<GroupEntry>> {
+        match &self.user {
+            Some(user) => self.lookup.groups(user),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+/// Maximum depth of nested sudoers includes
+const MAX_SUDOERS_DEPTH: usize = 8;
+
+/// Tags of sudoers commands, which apply to the following commands of a rule as well
+const SUDOERS_TAGS: &[&str] = &[
+    "NOPASSWD", "PASSWD", "NOEXEC", "EXEC", "SETENV", "NOSETENV", "LOG_INPUT", "NOLOG_INPUT",
+    "LOG_OUTPUT", "NOLOG_OUTPUT", "MAIL", "NOMAIL", "FOLLOW", "NOFOLLOW", "INTERCEPT", "NOINTERCEPT",
+];
+
+/// Checks if the sudoers file at `path` lets `account` run `ALL` commands as root without a password.
+///
+/// Rules which only allow some commands do not count. Aliases are not supported, so users are
+/// only matched by name, `#uid`, `%group`, `%#gid` and `ALL`, each of which can be negated with `!`.
+/// A missing file grants no rights.
+fn sudo_nopasswd(
+    path: &Path,
+    account: &Account,
+    root: Option<&Path>,
+    file_system: &dyn FileSystem,
+    depth: usize,
+) -> io::Result<bool> {
+    let contents = match file_system.read_to_string(path) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+        result => result?,
+    };
+    let in_root = |include: &str| {
+        let include = Path::new(include);
+        match (root, include.is_absolute()) {
+            (Some(root), true) => root.join(include.strip_prefix("/").unwrap_or(include)),
+            (_, true) => include.to_path_buf(),
+            (_, false) => path.parent().unwrap_or_else(|| Path::new("")).join(include),
+        }
+    };
+
+    for line in contents.replace("\\\n", " ").lines().map(str::trim) {
+        let mut words = line.split_whitespace();
+        let directive = words.next().unwrap_or_default();
+        let argument = words.next();
+        match (directive, argument) {
+            ("#include" | "@include", Some(file)) if depth < MAX_SUDOERS_DEPTH => {
+                if sudo_nopasswd(&in_root(file), account, root, file_system, depth + 1)? {
+                    return Ok(true);
+                }
+            }
+            ("#includedir" | "@includedir", Some(dir)) if depth < MAX_SUDOERS_DEPTH => {
+                let mut files = match file_system.read_dir(&in_root(dir)) {
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                    result => result?,
+                };
+                // sudo skips files containing a dot or ending with a tilde
+                files.retain(|file| file.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| !name.contains('.') && !name.ends_with('~')));
+                files.sort();
+                for file in files {
+                    if sudo_nopasswd(&file, account, root, file_system, depth + 1)? {
+                        return Ok(true);
+                    }
+                }
+            }
+            _ if line.is_empty() || directive.starts_with("Defaults") || directive.ends_with("_Alias") => {}
+            _ if line.starts_with('#') && !line[1..].starts_with(|c: char| c.is_ascii_digit()) => {}
+            _ => {
+                if sudoers_rule_grants(line, account)? {
+                    return Ok(true);
+                }
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Checks if the rule `users hosts = commands` lets `account` run `ALL` as root without a password.
+fn sudoers_rule_grants(rule: &str, account: &Account) -> io::Result<bool> {
+    let (users_and_hosts, commands) = match rule.split_once('=') {
+        Some(rule) => rule,
+        None => return Ok(false),
+    };
+    // lists may have spaces after their commas, only the space after the user list ends it
+    let users_and_hosts = users_and_hosts.split(',').map(str::trim).collect::<Vec<_>>().join(",");
+    let users = users_and_hosts.split_whitespace().next().unwrap_or_default();
+    if !sudoers_list_matches(users, |user| sudoers_user_matches(user, account))? {
+        return Ok(false);
+    }
+
+    // the runas user and the tags of a command also apply to the following ones
+    let mut runas_root = true;
+    let mut nopasswd = false;
+    for command in split_sudoers_commands(commands) {
+        let mut command = command.trim();
+        if let Some(spec) = command.strip_prefix('(') {
+            let (runas, rest) = spec.split_once(')').unwrap_or((spec, ""));
+            // without runas users, commands can only be run as the invoking user
+            let users = runas.split(':').next().unwrap_or_default();
+            runas_root = sudoers_list_matches(users, |user| Ok(matches!(user, "ALL" | "root" | "#0")))?;
+            command = rest.trim_start();
+        }
+        // options like `CWD=*` come before the tags
+        while let Some((option, rest)) = command.split_once(char::is_whitespace) {
+            match option.split_once('=') {
+                Some((name, _)) if !name.is_empty() && name.chars().all(|c| c.is_ascii_uppercase() || c == '_') => {
+                    command = rest.trim_start();
+                }
+                _ => break,
+            }
+        }
+        while let Some((tag, rest)) = command.split_once(':') {
+            if !SUDOERS_TAGS.contains(&tag.trim()) {
+                break;
+            }
+            match tag.trim() {
+                "NOPASSWD" => nopasswd = true,
+                "PASSWD" => nopasswd = false,
+                _ => {}
+            }
+            command = rest.trim_start();
+        }
+        if command == "ALL" && runas_root && nopasswd {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Splits the commands of a rule at the commas which are not part of a runas spec
+fn split_sudoers_commands(commands: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in commands.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&commands[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&commands[start..]);
+    parts
+}
+
+/// Checks a comma separated sudoers list, in which the last matching entry decides.
+/// Entries starting with `!` exclude the matching users.
+fn sudoers_list_matches<F>(list: &str, matches: F) -> io::Result<bool> where
+    F: Fn(&str) -> io::Result<bool>,
+{
+    let mut matched = false;
+    for entry in list.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+        let (negated, entry) = match entry.strip_prefix('!') {
+            Some(entry) => (true, entry.trim_start()),
+            None => (false, entry),
+        };
+        if matches(entry)? {
+            matched = !negated;
+        }
+    }
+    Ok(matched)
+}
+
+/// Checks if a user in the user list of a sudoers rule refers to `account`
+fn sudoers_user_matches(user: &str, account: &Account) -> io::Result<bool> {
+    Ok(if user == "ALL" {
+        true
+    } else if let Some(gid) = user.strip_prefix("%#") {
+        account.groups()?.iter().any(|group| gid.parse() == Ok(group.gid))
+    } else if let Some(name) = user.strip_prefix('%') {
+        account.groups()?.iter().any(|group| group.name == name)
+    } else if let Some(uid) = user.strip_prefix('#') {
+        account.uid.is_some() && uid.parse().ok() == account.uid
+    } else {
+        account.name.as_deref() == Some(user)
+    })
 }
 
 impl Default for IncludeAdministrative {
@@ -72,7 +568,9 @@ impl Default for IncludeAdministrative {
 
 #[cfg(test)]
 mod tests {
-    use crate::config::IncludeAdministrative;
+    use std::path::Path;
+
+    use crate::config::{Config, IncludeAdministrative, MemoryFileSystem, PasswdFiles, UidRange};
 
     #[test]
     fn test_default() {
@@ -85,4 +583,178 @@ mod tests {
             ])
         )
     }
+
+    #[test]
+    fn test_check_current_user() {
+        let lookup = PasswdFiles::parse(
+            "root:x:0:0::/root:/bin/sh\nalice:x:1000:1000::/home/alice:/bin/sh\nbob:x:1001:10::/home/bob:/bin/sh\n",
+            "root:x:0:\nwheel:x:10:\nsudo:x:27:alice\nalice:x:1000:\n",
+        );
+        let check = |policy: IncludeAdministrative, uid: u32| {
+            policy.check_current_user_with(&lookup.clone().with_current_uid(uid)).unwrap()
+        };
+        let groups = |groups: &[&str]| IncludeAdministrative::Groups(groups.iter().map(|g| g.to_string()).collect());
+
+        assert!(check(IncludeAdministrative::Always, 1000));
+        assert!(!check(IncludeAdministrative::Never, 0));
+        assert!(check(IncludeAdministrative::RootOnly, 0));
+        assert!(!check(IncludeAdministrative::RootOnly, 1000));
+        assert!(check(IncludeAdministrative::Users(vec!["alice".to_string()]), 1000));
+        assert!(!check(IncludeAdministrative::Users(vec!["alice".to_string()]), 1001));
+        // supplementary groups
+        assert!(check(groups(&["sudo"]), 1000));
+        // primary groups
+        assert!(check(groups(&["wheel"]), 1001));
+        assert!(!check(groups(&["wheel"]), 1000));
+        // numeric ids
+        assert!(check(groups(&["27"]), 1000));
+        assert!(!check(groups(&["27"]), 1001));
+        assert!(check(IncludeAdministrative::default(), 1000));
+        assert!(check(IncludeAdministrative::default(), 1001));
+        assert!(!check(IncludeAdministrative::default(), 0));
+
+        // users without a passwd entry only have an uid
+        assert!(check(IncludeAdministrative::Uids(vec![4242]), 4242));
+        assert!(!check(IncludeAdministrative::Users(vec!["alice".to_string()]), 4242));
+        assert!(!check(IncludeAdministrative::default(), 4242));
+        assert!(check(IncludeAdministrative::Not(Box::default()), 4242));
+
+        let wheel = groups(&["wheel"]);
+        assert!(wheel.check_user_with("bob", &lookup).unwrap());
+        assert!(!wheel.check_user_with("nobody", &lookup).unwrap());
+    }
+
+    #[test]
+    fn test_check_current_user_in() {
+        let root = Path::new("/srv/rootfs");
+        let uid = users::get_current_uid();
+        let fs = MemoryFileSystem::new()
+            .with_file(root.join("etc/passwd"), format!("builder:x:{}:4242::/home/builder:/bin/sh\n", uid))
+            .with_file(root.join("etc/group"), "users:x:4242:\nwheel:x:10:builder\nsudo:x:27:other\n");
+
+        let check = |include_administrative: IncludeAdministrative| {
+            include_administrative.check_in_file_system(None, root, &fs).unwrap()
+        };
+        assert!(check(IncludeAdministrative::Users(vec!["builder".to_string()])));
+        assert!(!check(IncludeAdministrative::Users(vec!["other".to_string()])));
+        assert!(check(IncludeAdministrative::Groups(vec!["users".to_string()])));
+        assert!(check(IncludeAdministrative::Groups(vec!["wheel".to_string()])));
+        assert!(!check(IncludeAdministrative::Groups(vec!["sudo".to_string()])));
+        assert!(!check(IncludeAdministrative::Never));
+
+        let wheel = IncludeAdministrative::Groups(vec!["wheel".to_string()]);
+        assert!(wheel.check_in_file_system(Some("builder"), root, &fs).unwrap());
+        assert!(!wheel.check_in_file_system(Some("nobody"), root, &fs).unwrap());
+    }
+
+    #[test]
+    fn test_combinators() {
+        let root = Path::new("/srv/rootfs");
+        let fs = MemoryFileSystem::new()
+            .with_file(
+                root.join("etc/passwd"),
+                "root:x:0:0::/root:/bin/sh\ndeploy:x:998:998::/srv:/bin/sh\nalice:x:1000:1000::/home/alice:/bin/sh\n",
+            )
+            .with_file(root.join("etc/group"), "root:x:0:\nwheel:x:10:alice\ndeploy:x:998:\nalice:x:1000:\n")
+            .with_file(
+                root.join("etc/sudoers"),
+                "# comment\nDefaults env_reset\nroot ALL=(ALL) ALL\n#998 ALL=(ALL) \\\n  NOPASSWD: ALL\n@includedir /etc/sudoers.d\n",
+            )
+            .with_file(root.join("etc/sudoers.d/wheel"), "%wheel ALL=(ALL) NOPASSWD: /usr/bin/apt\n")
+            .with_file(root.join("etc/sudoers.d/ignored.bak"), "ALL ALL=(ALL) NOPASSWD: ALL\n")
+            .with_file(root.join("etc/alice"), "alice ALL=(ALL) NOPASSWD: ALL\n");
+
+        let check = |policy: &IncludeAdministrative, user: &str| policy.check_in_file_system(Some(user), root, &fs).unwrap();
+        let wheel = IncludeAdministrative::Groups(vec!["wheel".to_string()]);
+        let deploy = IncludeAdministrative::Users(vec!["deploy".to_string()]);
+        let any = IncludeAdministrative::Any(vec![wheel.clone(), deploy.clone()]);
+        assert!(check(&any, "alice"));
+        assert!(check(&any, "deploy"));
+        assert!(!check(&any, "root"));
+        let all = IncludeAdministrative::All(vec![wheel.clone(), IncludeAdministrative::Not(Box::new(deploy))]);
+        assert!(check(&all, "alice"));
+        assert!(!check(&all, "deploy"));
+        assert!(check(&IncludeAdministrative::Not(Box::new(wheel)), "nobody"));
+
+        let system = IncludeAdministrative::UidRange(UidRange { min: None, max: Some(999) });
+        assert!(check(&system, "deploy"));
+        assert!(!check(&system, "alice"));
+        assert!(check(&IncludeAdministrative::Uids(vec![0]), "root"));
+        assert!(check(&IncludeAdministrative::Gids(vec![10]), "alice"));
+        assert!(!check(&IncludeAdministrative::Gids(vec![10]), "deploy"));
+
+        // rules which only allow some commands do not count
+        let sudo = IncludeAdministrative::SudoNopasswd("/etc/sudoers".into());
+        assert!(!check(&sudo, "alice"));
+        assert!(check(&sudo, "deploy"));
+        assert!(!check(&sudo, "root"));
+        assert!(check(&IncludeAdministrative::SudoNopasswd("/etc/alice".into()), "alice"));
+    }
+
+    #[test]
+    fn test_sudo_nopasswd_rules() {
+        let root = Path::new("/srv/rootfs");
+        let passwd = "root:x:0:0::/root:/bin/sh\nalice:x:1000:1000::/home/alice:/bin/sh\nbob:x:1001:1001::/home/bob:/bin/sh\n";
+        let check = |sudoers: &str, user: &str| {
+            let fs = MemoryFileSystem::new()
+                .with_file(root.join("etc/passwd"), passwd)
+                .with_file(root.join("etc/group"), "wheel:x:10:alice,bob\n")
+                .with_file(root.join("etc/sudoers"), sudoers);
+            IncludeAdministrative::SudoNopasswd("/etc/sudoers".into())
+                .check_in_file_system(Some(user), root, &fs)
+                .unwrap()
+        };
+
+        assert!(!check("alice ALL=(ALL) NOPASSWD: /usr/bin/apt\n", "alice"));
+        assert!(!check("alice ALL=(ALL) NOPASSWD: /usr/bin/apt, PASSWD: ALL\n", "alice"));
+        assert!(check("alice ALL=(ALL) NOPASSWD: /usr/bin/apt, ALL\n", "alice"));
+        assert!(!check("alice ALL=(ALL) ALL\n", "alice"));
+        assert!(check("alice ALL = NOPASSWD: ALL\n", "alice"));
+        assert!(check("alice ALL=(ALL:ALL) NOEXEC: NOPASSWD: ALL\n", "alice"));
+        assert!(check("alice ALL=(root, bob) CWD=* NOPASSWD: ALL\n", "alice"));
+        assert!(!check("alice ALL=(bob) NOPASSWD: ALL\n", "alice"));
+        assert!(!check("alice ALL=(:wheel) NOPASSWD: ALL\n", "alice"));
+        assert!(!check("alice ALL=(ALL, !root) NOPASSWD: ALL\n", "alice"));
+
+        // negations and lists with spaces
+        assert!(check("%wheel, !bob ALL=(ALL) NOPASSWD: ALL\n", "alice"));
+        assert!(!check("%wheel, !bob ALL=(ALL) NOPASSWD: ALL\n", "bob"));
+        assert!(!check("ALL,!alice ALL=(ALL) NOPASSWD: ALL\n", "alice"));
+        assert!(check("bob , alice host1, host2 = (ALL) NOPASSWD: ALL\n", "alice"));
+        assert!(!check("bob host1, alice = (ALL) NOPASSWD: ALL\n", "alice"));
+        assert!(!check("!alice ALL=(ALL) NOPASSWD: ALL\n", "alice"));
+    }
+
+    #[test]
+    fn test_serde() {
+        let config: Config = toml::from_str(
+            "include_administrative = { all = [ { not = \"root-only\" }, { uid-range = { min = 1000 } }, { sudo-nopasswd = \"/etc/sudoers\" } ] }"
+        ).unwrap();
+        assert_eq!(config.include_administrative, Some(IncludeAdministrative::All(vec![
+            IncludeAdministrative::Not(Box::new(IncludeAdministrative::RootOnly)),
+            IncludeAdministrative::UidRange(UidRange { min: Some(1000), max: None }),
+            IncludeAdministrative::SudoNopasswd("/etc/sudoers".into()),
+        ])));
+
+        let serialized = toml::to_string(&config).unwrap();
+        assert_eq!(toml::from_str::<Config>(&serialized).unwrap(), config);
+
+        // nested policies are written as arrays of tables
+        let nested = Config {
+            include_administrative: Some(IncludeAdministrative::Any(vec![
+                IncludeAdministrative::RootOnly,
+                IncludeAdministrative::All(vec![
+                    IncludeAdministrative::Groups(vec!["wheel".to_string()]),
+                    IncludeAdministrative::Not(Box::new(IncludeAdministrative::Always)),
+                ]),
+            ])),
+            ..Default::default()
+        };
+        let serialized = toml::to_string(&nested).unwrap();
+        assert!(serialized.contains("[[include_administrative.any.all]]\ngroups = [\"wheel\"]\n"), "{}", serialized);
+        assert_eq!(toml::from_str::<Config>(&serialized).unwrap(), nested);
+
+        assert!(toml::from_str::<Config>("include_administrative = \"users\"").is_err());
+        assert!(toml::from_str::<Config>("include_administrative = { users = [], groups = [] }").is_err());
+    }
 }
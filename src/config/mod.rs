@@ -3,24 +3,51 @@
 use serde::{Serialize, Deserialize};
 
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::{self, Read};
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::sync::Arc;
 
 mod include_administrative;
 
-pub use include_administrative::IncludeAdministrative;
+pub use include_administrative::{IncludeAdministrative, UidRange};
 
 mod path;
 
-pub use path::{Path, Paths};
+pub use path::{DedupMode, MissingPath, Path, Paths, PathsStyle, StyledPaths};
 
 mod path_flags;
 
 pub use path_flags::{PathFlags, PathOs, PathOsError, PathOsResult, ParsePathOsError, ParsePathOsResult};
 use std::str::FromStr;
 
+mod file_system;
+pub use file_system::{FileSystem, MemoryFileSystem, RealFileSystem};
+
+mod user_lookup;
+pub use user_lookup::{GroupEntry, PasswdFiles, SystemUsers, UserEntry, UserLookup};
+
+mod system_profile;
+pub use system_profile::SystemProfile;
+
 mod config_source;
-pub use config_source::ConfigSource;
+pub use config_source::{ConfigSource, ConfigFileDescription, ConfigFileFormat, PATH_HELPER_DIR, PATH_HELPER_FILE};
+
+mod expand;
+pub use expand::{expand, expand_with, references, ExpandError, ExpandResult};
+
+mod command;
+pub use command::{CommandCache, EnvCommand};
+
+mod env;
+pub use env::{resolve_env, resolve_env_cached, resolve_env_with, EnvError, EnvKind, EnvResult, EnvValue};
+
+mod exclude;
+pub use exclude::{Exclude, Excludes};
+
+mod result_cache;
+pub use result_cache::{clear_cache, CachedResult, ResultCache, ResultKey};
+
+mod dump;
 
 /// Main configuration file
 ///
@@ -31,12 +58,23 @@ pub struct Config {
     // Do not read in higher directories
     #[serde(default)]
     pub base: bool,
+    // Export all configured environment variables, not only the ones with `export = true`
     #[serde(default)]
+    pub export_env: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub include_administrative: Option<IncludeAdministrative>,
-    #[serde(default)]
+    #[serde(default, alias = "path")]
     pub paths: Paths,
-    #[serde(default)]
-    pub env: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Excludes::is_empty")]
+    pub exclude: Excludes,
+    #[serde(default, serialize_with = "serialize_env")]
+    pub env: HashMap<String, EnvValue>,
+    /// Source of the config which sets `base`
+    #[serde(skip)]
+    base_source: Option<Arc<ConfigSource>>,
+    /// Source of the config which sets `include_administrative`
+    #[serde(skip)]
+    include_administrative_source: Option<Arc<ConfigSource>>,
 }
 
 impl Config {
@@ -57,10 +95,36 @@ impl Config {
 
     pub fn included() -> Config {
         let mut config: Config = toml::from_str(include_str!("../config.toml")).unwrap();
-        config.paths.set_source(ConfigSource::Included);
+        config.set_source(ConfigSource::Included);
         config
     }
 
+    /// Sets the source of all settings in the config.
+    pub fn set_source(&mut self, source: ConfigSource) {
+        self.paths.set_source(source.clone());
+        self.exclude.set_source(source.clone());
+        let source = Arc::new(source);
+        for value in self.env.values_mut() {
+            value.set_source(source.clone());
+        }
+        if self.base {
+            self.base_source = Some(source.clone());
+        }
+        if self.include_administrative.is_some() {
+            self.include_administrative_source = Some(source);
+        }
+    }
+
+    /// Returns the source of the config which sets `base`
+    pub fn base_source(&self) -> Option<&Arc<ConfigSource>> {
+        self.base_source.as_ref()
+    }
+
+    /// Returns the source of the config which sets `include_administrative`
+    pub fn include_administrative_source(&self) -> Option<&Arc<ConfigSource>> {
+        self.include_administrative_source.as_ref()
+    }
+
     /// Read the config from a specific file.
     ///
     /// # Examples
@@ -72,22 +136,46 @@ impl Config {
     /// println!("{:?}", config);
     /// ```
     pub fn from_file<P: AsRef<std::path::Path>>(path: P) -> io::Result<Config> {
-        Config::from_file_inner(&path).map_err(
-            |err| io::Error::new(err.kind(), format!("{}: {}",
-                                                     path.as_ref().to_string_lossy(),
-                                                     err.to_string()
-            ))
-        )
+        Config::from_file_with(path, &RealFileSystem)
     }
 
-    fn from_file_inner<P: AsRef<std::path::Path>>(path: &P) -> io::Result<Config> {
-        let path_ref = path.as_ref();
-        let mut file = File::open(&path_ref)?;
-        let mut contents = Vec::new();
-        file.read_to_end(&mut contents)?;
+    /// Read the config from a file in `file_system`.
+    pub fn from_file_with<P: AsRef<std::path::Path>>(path: P, file_system: &dyn FileSystem) -> io::Result<Config> {
+        Config::from_file_inner(path.as_ref(), file_system, |contents| {
+            toml::from_slice(contents).map_err(|err| {
+                syntax_error(&err, err.line_col().map(|(line, column)| (line + 1, column + 1)))
+            })
+        })
+    }
 
-        let config: Config = toml::from_slice(&contents)?;
-        Ok(config)
+    /// Read the config from a JSON file in `file_system`, with the same structure as the TOML file.
+    #[cfg(feature = "json")]
+    pub fn from_json_with<P: AsRef<std::path::Path>>(path: P, file_system: &dyn FileSystem) -> io::Result<Config> {
+        Config::from_file_inner(path.as_ref(), file_system, |contents| {
+            serde_json::from_slice(contents).map_err(|err| {
+                syntax_error(&err, Some((err.line(), err.column())).filter(|&(line, _)| line > 0))
+            })
+        })
+    }
+
+    /// Read the config from a YAML file in `file_system`, with the same structure as the TOML file.
+    #[cfg(feature = "yaml")]
+    pub fn from_yaml_with<P: AsRef<std::path::Path>>(path: P, file_system: &dyn FileSystem) -> io::Result<Config> {
+        Config::from_file_inner(path.as_ref(), file_system, |contents| {
+            serde_yaml::from_slice(contents).map_err(|err| {
+                syntax_error(&err, err.location().map(|location| (location.line(), location.column())))
+            })
+        })
+    }
+
+    fn from_file_inner(
+        path: &std::path::Path,
+        file_system: &dyn FileSystem,
+        parse: fn(&[u8]) -> io::Result<Config>,
+    ) -> io::Result<Config> {
+        file_system.read(path)
+            .and_then(|contents| parse(&contents))
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", path.to_string_lossy(), err)))
     }
 
     /// Read the config from a simple txt file
@@ -100,19 +188,18 @@ impl Config {
     /// Config::from_txt("src/config.txt").unwrap();
     /// ```
     pub fn from_txt<P: AsRef<std::path::Path>>(path: P) -> io::Result<Config> {
-        let path_ref = path.as_ref();
-        let mut file = File::open(&path_ref)?;
-        let mut contents = Vec::new();
-        file.read_to_end(&mut contents)?;
-        let contents_str = String::from_utf8(contents).map_err(
-            |err| io::Error::new(io::ErrorKind::InvalidData, err.to_string())
-        )?;
+        Config::from_txt_with(path, &RealFileSystem)
+    }
+
+    /// Read the config from a simple txt file in `file_system`.
+    pub fn from_txt_with<P: AsRef<std::path::Path>>(path: P, file_system: &dyn FileSystem) -> io::Result<Config> {
+        let contents_str = file_system.read_to_string(path.as_ref())?;
 
         let paths: io::Result<Vec<_>> = contents_str.lines()
-            .map(|line| line.splitn(2, '#').next().unwrap())
+            .map(|line| line.split('#').next().unwrap())
             .map(str::trim)
             .filter(|s| !s.is_empty())
-            .map(|line| Path::from_str(line))
+            .map(Path::from_str)
             .collect();
 
         Ok(Config {
@@ -121,6 +208,119 @@ impl Config {
         })
     }
 
+    /// Read the paths from a file in the format of `/etc/paths`, with one directory per line.
+    ///
+    /// Unlike the txt format, lines are used literally without comments or flags.
+    pub fn from_paths_file_with<P: AsRef<std::path::Path>>(path: P, file_system: &dyn FileSystem) -> io::Result<Config> {
+        let contents_str = file_system.read_to_string(path.as_ref())?;
+
+        let paths = contents_str.lines()
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(Path::from)
+            .collect();
+
+        Ok(Config {
+            paths,
+            ..Default::default()
+        })
+    }
+
+    /// Reads `/etc/paths` and the files in `/etc/paths.d` in alphabetical order, below the root
+    /// of `profile`, like macOS `path_helper`.
+    ///
+    /// Missing files are skipped. The paths keep the file they are read from as their source.
+    pub fn from_path_helper(profile: &SystemProfile) -> io::Result<Config> {
+        let file_system = profile.file_system();
+        let mut files = vec![profile.in_root(std::path::Path::new(PATH_HELPER_FILE))];
+        match file_system.read_dir(&profile.in_root(std::path::Path::new(PATH_HELPER_DIR))) {
+            Ok(mut entries) => {
+                entries.sort();
+                files.extend(entries.into_iter().filter(|entry| !file_system.is_dir(entry)));
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+
+        let mut config = Config::new();
+        for file in files {
+            let mut file_config = match Config::from_paths_file_with(&file, file_system) {
+                Ok(file_config) => file_config,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err),
+            };
+            file_config.set_source(ConfigSource::Config(ConfigFileDescription::new(&file, ConfigFileFormat::Paths)));
+            config.paths.extend(file_config.paths);
+        }
+        Ok(config)
+    }
+
+    /// Writes the config in `format`, keeping the order of the paths.
+    ///
+    /// Settings which `format` can not represent are left out, see `unsupported_in`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use pathfix::config::{Config, ConfigFileFormat};
+    ///
+    /// let config: Config = toml::from_str("paths = [\"~/bin\", { path = \"/usr/sbin\", flags = \"unix\" }]").unwrap();
+    /// assert_eq!(config.serialize_as(ConfigFileFormat::Text).unwrap(), "~/bin\n/usr/sbin|unix\n");
+    /// ```
+    pub fn serialize_as(&self, format: ConfigFileFormat) -> io::Result<String> {
+        let invalid_data = |err: &dyn std::fmt::Display| io::Error::new(io::ErrorKind::InvalidData, err.to_string());
+        match format {
+            ConfigFileFormat::Toml => toml::to_string(self).map_err(|err| invalid_data(&err)),
+            ConfigFileFormat::Text => Ok(self.paths.iter()
+                .map(|path| format!("{}\n", path))
+                .collect()),
+            ConfigFileFormat::Paths => Ok(self.paths.iter()
+                .map(|path| format!("{}\n", path.path().to_string_lossy()))
+                .collect()),
+            #[cfg(feature = "json")]
+            ConfigFileFormat::Json => serde_json::to_string_pretty(self)
+                .map(|json| json + "\n")
+                .map_err(|err| invalid_data(&err)),
+            #[cfg(feature = "yaml")]
+            ConfigFileFormat::Yaml => serde_yaml::to_string(self).map_err(|err| invalid_data(&err)),
+        }
+    }
+
+    /// Describes the settings which are lost when the config is written in `format`
+    pub fn unsupported_in(&self, format: ConfigFileFormat) -> Vec<String> {
+        // only the line based formats can not represent everything
+        if !matches!(format, ConfigFileFormat::Text | ConfigFileFormat::Paths) {
+            return Vec::new();
+        }
+
+        let mut unsupported = Vec::new();
+        let mut setting = |name: &str, used: bool| if used {
+            unsupported.push(name.to_string());
+        };
+        setting("base", self.base);
+        setting("export_env", self.export_env);
+        setting("include_administrative", self.include_administrative.is_some());
+        setting("[exclude]", !self.exclude.is_empty());
+        setting("[env]", !self.env.is_empty());
+        for path in &self.paths {
+            let name = path.path().to_string_lossy();
+            let mut attribute = |attribute: &str, used: bool| if used {
+                unsupported.push(format!("{} of path {}", attribute, name));
+            };
+            attribute("comment", path.comment().is_some());
+            attribute("priority", path.priority() != 0);
+            attribute("condition", path.condition().is_some());
+            if format == ConfigFileFormat::Paths {
+                attribute("flags", path.flags() != PathFlags::default());
+                attribute("required", path.required());
+            } else if name.contains('#') || name.contains('|') {
+                // `#` starts a comment and `|` the flags in the txt format
+                unsupported.push(format!("path {}", name));
+            }
+        }
+        unsupported
+    }
+
     /// Sets the env parameter with the system environment.
     ///
     /// All existing variables in the config will be overwritten.
@@ -138,28 +338,179 @@ impl Config {
     /// assert_eq!(config.env["FOO"], "BAR");
     /// ```
     pub fn with_env(mut self) -> Config {
-        self.env = std::env::vars().collect();
+        self.env = std::env::vars_os()
+            .filter_map(|(name, value)| Some((name.into_string().ok()?, EnvValue::literal(value))))
+            .collect();
         self
     }
 
+    /// Expands the values of all variables against each other.
+    ///
+    /// See `resolve_env` for details.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use pathfix::config::{Config, EnvValue};
+    ///
+    /// let mut config = Config::new();
+    /// config.env.insert("HOME".to_string(), EnvValue::literal("/home/user"));
+    /// config.env.insert("GOPATH".to_string(), EnvValue::new("$HOME/go"));
+    /// assert_eq!(config.resolve_env().unwrap()["GOPATH"], "/home/user/go");
+    /// ```
+    pub fn resolve_env(&self) -> EnvResult<HashMap<String, OsString>> {
+        resolve_env(&self.env)
+    }
+
+    /// Returns the names of all variables the config refers to, without duplicates.
+    ///
+    /// This includes the variables of `PATH` entries, conditions, exclusion rules and
+    /// the names and references of environment variables which are not literals.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use pathfix::config::Config;
+    ///
+    /// let config: Config = toml::from_str(r#"
+    ///     paths = ["~/bin", "$GOPATH/bin"]
+    ///     [env]
+    ///     GOPATH = "${XDG_DATA_HOME:-$HOME/.local/share}/go"
+    /// "#).unwrap();
+    /// assert_eq!(config.references(), vec!["HOME", "GOPATH", "XDG_DATA_HOME"]);
+    /// ```
+    pub fn references(&self) -> Vec<String> {
+        let paths = self.paths.iter().flat_map(|path| {
+            references(&path.path().to_string_lossy()).into_iter()
+                .chain(path.condition().map(str::to_string))
+        });
+        let excludes = self.exclude.0.iter()
+            .flat_map(|exclude| exclude.literal.iter())
+            .flat_map(|literal| references(literal));
+        let mut env: Vec<_> = self.env.iter()
+            .filter(|(_, value)| !value.is_literal())
+            .collect();
+        env.sort_by_key(|(name, _)| *name);
+        let env = env.into_iter()
+            .flat_map(|(name, value)| std::iter::once(name.clone()).chain(value.references()));
+
+        let mut names: Vec<String> = Vec::new();
+        for name in paths.chain(excludes).chain(env) {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        names
+    }
+
+    /// Returns the variables which should be exported to the shell, sorted by name.
+    ///
+    /// These are the configured variables with `export = true`, or all configured
+    /// variables if `export_env` is set. Variables from the process environment
+    /// and variables which are missing in the resolved `env` are left out.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use pathfix::config::{Config, EnvValue};
+    ///
+    /// let mut config = Config::new().with_env();
+    /// config.env.insert("GOROOT".to_string(), EnvValue::new("/usr/local/go").exported());
+    /// config.env.insert("GOPATH".to_string(), EnvValue::new("$HOME/go"));
+    /// let env = config.resolve_env().unwrap();
+    /// assert_eq!(config.exports(&env), vec![("GOROOT", "/usr/local/go".as_ref())]);
+    /// ```
+    pub fn exports<'a>(&'a self, env: &'a HashMap<String, OsString>) -> Vec<(&'a str, &'a OsStr)> {
+        let mut exports: Vec<_> = self.env.iter()
+            .filter(|(_, value)| !value.is_literal() && (value.export() || self.export_env))
+            .filter_map(|(name, _)| Some((name.as_str(), env.get(name)?.as_os_str())))
+            .collect();
+        exports.sort();
+        exports
+    }
+
     /// Merges two `Config` structures.
     /// Changes in the `other` Config will overwrite
     /// vaules in `self`.
+    ///
+    /// Environment variables in `self` are kept if they are marked as overriding,
+    /// but the ones in `other` are not.
     pub fn merge(self, other: Config) -> Config {
+        let (include_administrative, include_administrative_source) = match other.include_administrative {
+            Some(include_administrative) => (Some(include_administrative), other.include_administrative_source),
+            None => (self.include_administrative, self.include_administrative_source),
+        };
         Config {
             base: self.base || other.base,
-            include_administrative: other.include_administrative
-                .or(self.include_administrative),
+            export_env: self.export_env || other.export_env,
+            include_administrative,
             paths: self.paths.merge(other.paths),
-            env: self.env.into_iter().chain(other.env).collect(),
+            exclude: self.exclude.merge(other.exclude),
+            env: env::merge_env(self.env, other.env),
+            base_source: if other.base { other.base_source } else { self.base_source },
+            include_administrative_source,
+        }
+    }
+}
+
+/// Serializes the variables sorted by name, with tables after plain values as required by TOML
+fn serialize_env<S: serde::Serializer>(env: &HashMap<String, EnvValue>, serializer: S) -> Result<S::Ok, S::Error> {
+    let sorted: std::collections::BTreeMap<_, _> = env.iter().collect();
+    toml::ser::tables_last(&sorted, serializer)
+}
+
+/// Formats `value` in a single line, e.g. `{ groups = ["wheel"] }`
+pub(crate) fn inline_toml(value: &toml::Value) -> String {
+    match value {
+        toml::Value::Array(values) => {
+            format!("[{}]", values.iter().map(inline_toml).collect::<Vec<_>>().join(", "))
         }
+        toml::Value::Table(table) if table.is_empty() => "{}".to_string(),
+        toml::Value::Table(table) => {
+            let entries: Vec<_> = table.iter()
+                .map(|(key, value)| format!("{} = {}", toml_key(key), inline_toml(value)))
+                .collect();
+            format!("{{ {} }}", entries.join(", "))
+        }
+        value => value.to_string(),
+    }
+}
+
+/// Quotes `key` if it is not a bare key
+pub(crate) fn toml_key(key: &str) -> String {
+    let bare = !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if bare {
+        key.to_string()
+    } else {
+        toml::Value::String(key.to_string()).to_string()
     }
 }
 
+/// Reports an error of a configuration file the same way for all formats.
+///
+/// `position` is the line and column, starting at 1.
+fn syntax_error(err: &dyn std::fmt::Display, position: Option<(usize, usize)>) -> io::Error {
+    let mut message = err.to_string();
+    // the parsers append the position as " at line 1 column 2", but not consistently
+    if let Some(index) = message.rfind(" at line ") {
+        if message[index + " at line ".len()..].split(" column ").all(|n| n.parse::<usize>().is_ok()) {
+            message.truncate(index);
+        }
+    }
+    if let Some((line, column)) = position {
+        message = format!("line {}, column {}: {}", line, column, message);
+    }
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::config::{Config, IncludeAdministrative, Paths};
-    use crate::config::ConfigSource;
+    use std::collections::HashMap;
+    use std::io;
+    use std::path::PathBuf;
+
+    use crate::config::{Config, EnvValue, Excludes, IncludeAdministrative, Paths};
+    use crate::config::{ConfigFileDescription, ConfigFileFormat, ConfigSource, MemoryFileSystem, SystemProfile};
 
     #[test]
     fn test_new() {
@@ -169,7 +520,7 @@ mod tests {
     #[test]
     fn test_from_file() {
         let mut config = Config::from_file("src/config.toml").unwrap();
-        config.paths.set_source(ConfigSource::Included);
+        config.set_source(ConfigSource::Included);
         let wanted = Config::included();
         assert_eq!(config, wanted);
     }
@@ -194,27 +545,248 @@ mod tests {
     fn test_merge() {
         let config1 = Config {
             base: true,
+            export_env: true,
             include_administrative: Some(IncludeAdministrative::Always),
             paths: vec!["/foo/bar", "/bar/bazz"].into(),
-            env: vec![("FOO".to_string(), "BAR".to_string())].into_iter().collect(),
+            exclude: Excludes::default(),
+            env: vec![("FOO".to_string(), EnvValue::from("BAR"))].into_iter().collect(),
+            ..Default::default()
         };
         let config2 = Config {
             base: true,
+            export_env: false,
             include_administrative: Some(IncludeAdministrative::RootOnly),
             paths: Paths::from(vec!["/fnort"]),
-            env: vec![("FOO".to_string(), "FNAFF".to_string())].into_iter().collect(),
+            exclude: Excludes::default(),
+            env: vec![("FOO".to_string(), EnvValue::from("FNAFF"))].into_iter().collect(),
+            ..Default::default()
         };
         let result = Config {
             base: true,
+            export_env: true,
             include_administrative: Some(IncludeAdministrative::RootOnly),
             paths: vec!["/fnort", "/foo/bar", "/bar/bazz"].into(),
-            env: vec![("FOO".to_string(), "FNAFF".to_string())].into_iter().collect(),
+            exclude: Excludes::default(),
+            env: vec![("FOO".to_string(), EnvValue::from("FNAFF"))].into_iter().collect(),
+            ..Default::default()
         };
         assert_eq!(config1.merge(config2), result);
     }
 
+    #[test]
+    fn test_path_tables() {
+        let config: Config = toml::from_str(r#"
+            [[path]]
+            path = "/foo/bar"
+            flags = "unix"
+        "#).unwrap();
+        assert_eq!(config.paths, Paths::new(vec!["/foo/bar|unix".parse().unwrap()]));
+    }
+
     #[test]
     fn test_included() {
         Config::included();
     }
+
+    #[test]
+    fn test_memory_file_system() {
+        let file_system = MemoryFileSystem::new()
+            .with_file("/etc/pathfix.toml", r#"
+                base = true
+                include_administrative = { groups = ["wheel"] }
+
+                [paths]
+                "~/bin" = ""
+                "/usr/local/sbin" = "admin"
+                "/usr/bin" = ""
+                "/opt/missing/bin" = ""
+            "#)
+            .with_file("/etc/passwd", "alice:x:1000:1000::/home/alice:/bin/sh\nbob:x:1001:1001::/home/bob:/bin/sh\n")
+            .with_file("/etc/group", "wheel:x:10:alice\n")
+            .with_dir("/home/alice/bin")
+            .with_dir("/usr/local/sbin")
+            .with_symlink("/usr/bin", "/bin")
+            .with_dir("/bin");
+        let env: HashMap<String, _> = vec![("HOME".to_string(), "/home/alice".into())].into_iter().collect();
+
+        let generate = |user: &str| {
+            let profile = SystemProfile::this_system()
+                .with_root("/")
+                .with_user(user)
+                .with_file_system(file_system.clone());
+            let config = ConfigSource::System(ConfigFileFormat::Toml).open_for(&profile).unwrap();
+            let flags = profile.path_flags(config.include_administrative.as_ref().unwrap());
+            let mut path = config.paths.resolve(flags, &env);
+            path.retain(|path| profile.exists(path));
+            path
+        };
+        let path = |path: &[&str]| path.iter().map(PathBuf::from).collect::<Vec<_>>();
+        assert_eq!(generate("alice"), path(&["/home/alice/bin", "/usr/local/sbin", "/usr/bin"]));
+        assert_eq!(generate("bob"), path(&["/home/alice/bin", "/usr/bin"]));
+    }
+
+    #[test]
+    fn test_path_helper() {
+        let file_system = MemoryFileSystem::new()
+            .with_file("/mac/etc/paths", "/usr/local/bin\n/usr/bin\n\n  /bin  \n")
+            .with_file("/mac/etc/paths.d/TeX", "/Library/TeX/texbin\n")
+            .with_file("/mac/etc/paths.d/100-rvictl", "/Library/Apple/usr/bin\n")
+            .with_dir("/mac/etc/paths.d/empty");
+        let profile = SystemProfile::this_system()
+            .with_root("/mac")
+            .with_file_system(file_system);
+
+        let config = ConfigSource::PathHelper.open_for(&profile).unwrap();
+        let paths: Vec<_> = config.paths.iter().map(|path| path.path().to_os_string()).collect();
+        assert_eq!(paths, vec!["/usr/local/bin", "/usr/bin", "/bin", "/Library/Apple/usr/bin", "/Library/TeX/texbin"]);
+        let source = config.paths.iter().last().unwrap().source().unwrap();
+        assert_eq!(**source, ConfigSource::Config(ConfigFileDescription::new("/mac/etc/paths.d/TeX", ConfigFileFormat::Paths)));
+
+        let config = ConfigSource::System(ConfigFileFormat::Paths).open_for(&profile).unwrap();
+        assert_eq!(config.paths.len(), 3);
+
+        let empty = SystemProfile::this_system()
+            .with_root("/")
+            .with_file_system(MemoryFileSystem::new());
+        assert!(ConfigSource::PathHelper.open_for(&empty).unwrap().paths.is_empty());
+    }
+
+    #[test]
+    fn test_paths_file_description() {
+        let parse = |s: &str| s.parse::<ConfigFileDescription>().unwrap();
+        for file in &["/etc/paths", "/etc/paths.d/TeX", "local.paths"] {
+            assert_eq!(parse(file), ConfigFileDescription::new(file, ConfigFileFormat::Paths));
+        }
+        for file in &["/etc/pathfix.toml", "/etc/paths.d/git.toml"] {
+            assert_eq!(parse(file), ConfigFileDescription::new(file, ConfigFileFormat::Toml));
+        }
+        assert_eq!(parse("/etc/paths.d/git.txt"), ConfigFileDescription::new("/etc/paths.d/git.txt", ConfigFileFormat::Text));
+
+        assert!("/etc/pathfix".parse::<ConfigFileDescription>().is_err());
+        let extensionless = ConfigFileDescription::from_path("/etc/pathfix").unwrap();
+        assert_eq!(extensionless, ConfigFileDescription::new("/etc/pathfix", ConfigFileFormat::Toml));
+
+        let err = ConfigFileDescription::from_path("/tmp/pf.conf").unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(err.to_string().contains("'toml', 'txt', 'paths', 'json' or 'yaml'"), "{}", err);
+    }
+
+    #[cfg(any(feature = "json", feature = "yaml"))]
+    const TOML_CONFIG: &str = r#"
+base = true
+include_administrative = { groups = ["wheel"] }
+
+[paths]
+"~/bin" = ""
+"/usr/local/sbin" = "admin"
+
+[env]
+GOPATH = "~/go"
+"#;
+
+    #[test]
+    fn test_syntax_error() {
+        let file_system = MemoryFileSystem::new()
+            .with_file("/a.toml", "base = true\npaths = [\"/bin\"\n");
+        let err = Config::from_file_with("/a.toml", &file_system).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().starts_with("/a.toml: line 3, column 1: "), "{}", err);
+        assert!(!err.to_string().contains(" at line "), "{}", err);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_json() {
+        let file_system = MemoryFileSystem::new()
+            .with_file("/a.json", r#"{
+                "base": true,
+                "include_administrative": { "groups": ["wheel"] },
+                "paths": { "~/bin": "", "/usr/local/sbin": "admin" },
+                "env": { "GOPATH": "~/go" }
+            }"#)
+            .with_file("/b.json", "{\n  \"base\": 1\n}\n");
+        let expected: Config = toml::from_str(TOML_CONFIG).unwrap();
+        assert_eq!(Config::from_json_with("/a.json", &file_system).unwrap(), expected);
+        let json = expected.serialize_as(ConfigFileFormat::Json).unwrap();
+        assert_eq!(serde_json::from_str::<Config>(&json).unwrap(), expected);
+
+        let err = Config::from_json_with("/b.json", &file_system).unwrap_err();
+        assert!(err.to_string().starts_with("/b.json: line 2, column "), "{}", err);
+        assert!(!err.to_string().contains(" at line "), "{}", err);
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn test_yaml() {
+        let file_system = MemoryFileSystem::new()
+            .with_file("/a.yaml", "\
+base: true
+include_administrative:
+  groups: [wheel]
+paths:
+  ~/bin: ''
+  /usr/local/sbin: admin
+env:
+  GOPATH: ~/go
+")
+            .with_file("/b.yaml", "paths: []\nbase: 1\n");
+        let expected: Config = toml::from_str(TOML_CONFIG).unwrap();
+        assert_eq!(Config::from_yaml_with("/a.yaml", &file_system).unwrap(), expected);
+        let yaml = expected.serialize_as(ConfigFileFormat::Yaml).unwrap();
+        assert_eq!(serde_yaml::from_str::<Config>(&yaml).unwrap(), expected);
+
+        let err = Config::from_yaml_with("/b.yaml", &file_system).unwrap_err();
+        assert!(err.to_string().starts_with("/b.yaml: line 2, column "), "{}", err);
+        assert!(!err.to_string().contains(" at line "), "{}", err);
+    }
+
+    #[test]
+    fn test_serialize_as() {
+        let config: Config = toml::from_str(r#"
+            base = true
+            include_administrative = { any = [ { groups = ["wheel"] }, { uid-range = { max = 999 } } ] }
+
+            [[paths]]
+            path = "~/bin"
+
+            [[paths]]
+            path = "/opt/corp/bin"
+            flags = "unix"
+            required = true
+            priority = 10
+
+            [exclude]
+            literal = ["."]
+
+            [env]
+            GOPATH = "~/go"
+            BREW_PREFIX = { command = ["brew", "--prefix"] }
+        "#).unwrap();
+
+        let toml = config.serialize_as(ConfigFileFormat::Toml).unwrap();
+        assert_eq!(toml::from_str::<Config>(&toml).unwrap(), config);
+        assert!(config.unsupported_in(ConfigFileFormat::Toml).is_empty());
+
+        assert_eq!(config.serialize_as(ConfigFileFormat::Text).unwrap(), "~/bin\n/opt/corp/bin!|unix\n");
+        assert_eq!(config.unsupported_in(ConfigFileFormat::Text), vec![
+            "base", "include_administrative", "[exclude]", "[env]", "priority of path /opt/corp/bin",
+        ]);
+        assert_eq!(config.serialize_as(ConfigFileFormat::Paths).unwrap(), "~/bin\n/opt/corp/bin\n");
+        assert_eq!(config.unsupported_in(ConfigFileFormat::Paths)[4..].to_vec(), vec![
+            "priority of path /opt/corp/bin", "flags of path /opt/corp/bin", "required of path /opt/corp/bin",
+        ]);
+    }
+
+    #[test]
+    fn test_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Config>();
+        assert_send_sync::<SystemProfile>();
+        assert_send_sync::<crate::Resolution>();
+
+        static CONFIG: std::sync::OnceLock<Config> = std::sync::OnceLock::new();
+        let config = CONFIG.get_or_init(Config::included);
+        let paths = std::thread::spawn(move || config.paths.clone()).join().unwrap();
+        assert!(paths.iter().all(|path| path.source() == Some(&std::sync::Arc::new(ConfigSource::Included))));
+    }
 }
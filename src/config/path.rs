@@ -1,66 +1,139 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::iter::FromIterator;
 
 use serde::{Deserialize, Serialize, Serializer, Deserializer};
 use std::env::VarError;
-use std::rc::Rc;
+use std::sync::Arc;
 use serde::ser::SerializeMap;
-use serde::de::{Visitor, MapAccess};
+use serde::ser::{Error as _, SerializeSeq};
+use serde::de::{Visitor, MapAccess, SeqAccess};
 use core::fmt;
 use std::fmt::Display;
 use std::str::FromStr;
 use std::io;
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::PathBuf;
 
-use crate::config::{ConfigSource, PathFlags};
+use regex::bytes::Regex;
+
+use crate::config::{expand_with, ConfigSource, ExpandError, ExpandResult, FileSystem, PathFlags, SystemUsers, UserLookup};
+use crate::config::exclude::glob_to_regex;
 
 /// Single entry in the to be generated _$PATH_ variable.
 ///
 /// This type includes the path, the flags that regulate if it should be added
 /// and the source of the Path (configuration file, environment, ...)
+///
+/// Besides the flags, an entry can carry additional attributes, which are only
+/// available in the `[[paths]]` array-of-tables configuration syntax.
 #[derive(Default, Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
 pub struct Path {
-    path: String,
+    #[serde(serialize_with = "serialize_os_str", deserialize_with = "deserialize_os_string")]
+    path: OsString,
+    #[serde(default)]
     flags: PathFlags,
+    /// Free text describing the entry
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
+    /// The entry is expected to exist on the system
+    #[serde(default, skip_serializing_if = "is_false")]
+    required: bool,
+    /// Entries with a higher priority are placed in front of entries with a lower one
+    #[serde(default, skip_serializing_if = "is_zero")]
+    priority: i32,
+    /// Name of an environment variable which must be set to include the entry
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    condition: Option<String>,
     #[serde(skip)]
-    source: Option<Rc<ConfigSource>>,
+    source: Option<Arc<ConfigSource>>,
+}
+
+fn is_false(b: &bool) -> bool {
+    !*b
+}
+
+fn is_zero(n: &i32) -> bool {
+    *n == 0
+}
+
+fn serialize_os_str<S>(s: &OsStr, serializer: S) -> Result<S::Ok, S::Error> where
+    S: Serializer,
+{
+    s.to_str()
+        .ok_or_else(|| S::Error::custom(format!("path {} is not valid UTF-8", s.to_string_lossy())))?
+        .serialize(serializer)
+}
+
+fn deserialize_os_string<'de, D>(deserializer: D) -> Result<OsString, D::Error> where
+    D: Deserializer<'de>,
+{
+    String::deserialize(deserializer).map(OsString::from)
 }
 
 impl Path {
     pub fn new<S>(path: S, flags: PathFlags) -> Path
-        where S: ToString {
+        where S: Into<OsString> {
         Path {
-            path: path.to_string(),
+            path: path.into(),
             flags,
-            source: None,
+            ..Default::default()
         }
     }
 
     pub fn with_source<S, C> (path: S, flags: PathFlags, source: C) -> Path
-        where S: ToString, C: Into<Rc<ConfigSource>> {
+        where S: Into<OsString>, C: Into<Arc<ConfigSource>> {
         Path {
-            path: path.to_string(),
+            path: path.into(),
             flags,
             source: Some(source.into()),
+            ..Default::default()
         }
     }
 
-    pub fn resolve(&self, env: &HashMap<String, String>) -> Option<String> {
-        let path: Option<Vec<String>> = self.path
-            .split('/')
-            .map(|folder| {
-                if folder == "~" {
-                    env.get("HOME").map(String::to_string)
-                } else if let Some(env_name) = folder.strip_prefix('$') {
-                    env.get(env_name).map(String::to_string)
-                } else {
-                    Some(folder.to_string())
-                }
+    /// Resolves the path by expanding variables and a leading tilde.
+    ///
+    /// Returns `None` if the path can't be expanded, see `Path::try_resolve`.
+    pub fn resolve(&self, env: &HashMap<String, OsString>) -> Option<PathBuf> {
+        self.resolve_with(env, &SystemUsers)
+    }
+
+    /// Like `resolve`, but looks up the home directories of `~user` in `users`.
+    pub fn resolve_with(&self, env: &HashMap<String, OsString>, users: &dyn UserLookup) -> Option<PathBuf> {
+        self.try_resolve_with(env, users)
+            .map_err(|err| match err {
+                ExpandError::Unset(_) => debug!("Can not resolve {}: {}", self.path.to_string_lossy(), err),
+                _ => warn!("Can not resolve {}: {}", self.path.to_string_lossy(), err),
             })
-            .collect();
-        path.map(|path| path.join("/"))
+            .ok()
+    }
+
+    /// Resolves the path by expanding variables and a leading tilde.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use pathfix::config::Path;
+    ///
+    /// let mut env = HashMap::new();
+    /// env.insert("HOME".to_string(), "/home/user".into());
+    ///
+    /// assert_eq!(Path::from("~/bin").try_resolve(&env).unwrap().to_str(), Some("/home/user/bin"));
+    /// assert_eq!(Path::from("${HOME}/.local/bin").try_resolve(&env).unwrap().to_str(), Some("/home/user/.local/bin"));
+    /// ```
+    pub fn try_resolve(&self, env: &HashMap<String, OsString>) -> ExpandResult<PathBuf> {
+        self.try_resolve_with(env, &SystemUsers)
+    }
+
+    /// Like `try_resolve`, but looks up the home directories of `~user` in `users`.
+    pub fn try_resolve_with(&self, env: &HashMap<String, OsString>, users: &dyn UserLookup) -> ExpandResult<PathBuf> {
+        expand_with(&self.path, env, users).map(PathBuf::from)
     }
 
     /// Returns the contained path string
-    pub fn path(&self) -> &str {
+    pub fn path(&self) -> &OsStr {
         &self.path
     }
 
@@ -68,8 +141,57 @@ impl Path {
         self.flags
     }
 
+    /// Returns the comment describing the path
+    pub fn comment(&self) -> Option<&str> {
+        self.comment.as_deref()
+    }
+
+    /// Returns if the path is expected to exist
+    pub fn required(&self) -> bool {
+        self.required
+    }
+
+    /// Returns the priority of the path
+    ///
+    /// The default priority is `0`.
+    pub fn priority(&self) -> i32 {
+        self.priority
+    }
+
+    /// Returns the name of the environment variable the path depends on
+    pub fn condition(&self) -> Option<&str> {
+        self.condition.as_deref()
+    }
+
+    /// Checks if the path only consists of a path string and flags
+    ///
+    /// Only those paths can be written in the `path = "flags"` map syntax.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use pathfix::config::{Path, PathFlags};
+    ///
+    /// assert!(Path::new("/foo/bar", PathFlags::new()).is_simple());
+    /// ```
+    pub fn is_simple(&self) -> bool {
+        self.comment.is_none()
+            && !self.required
+            && self.priority == 0
+            && self.condition.is_none()
+    }
+
+    /// Checks if the condition of the path is met by the environment
+    ///
+    /// Paths without a condition are always included.
+    pub fn check_condition(&self, env: &HashMap<String, OsString>) -> bool {
+        self.condition.as_ref()
+            .map(|var| env.get(var).map(|value| !value.is_empty()).unwrap_or(false))
+            .unwrap_or(true)
+    }
+
     /// Returns the source of where the path originates from
-    pub fn source(&self) -> Option<&Rc<ConfigSource>> {
+    pub fn source(&self) -> Option<&Arc<ConfigSource>> {
         self.source.as_ref()
     }
 
@@ -86,39 +208,51 @@ impl Path {
     /// ```
     pub fn normalize(self) -> Path {
         Path {
-            path: self.path,
-            flags: self.flags,
             source: None,
+            ..self
         }
     }
 }
 
 impl Display for Path {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path().to_string_lossy())?;
+        if self.required {
+            write!(f, "!")?;
+        }
         if self.flags() != PathFlags::default() {
-            write!(f, "{}|{}", self.path(), self.flags())
-        } else {
-            write!(f, "{}", self.path())
+            write!(f, "|{}", self.flags())?;
         }
+        Ok(())
     }
 }
 
 impl FromStr for Path {
     type Err = io::Error;
 
+    /// Parses the `path|flags` syntax of text configuration files.
+    ///
+    /// A `!` directly after the path marks it as required.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let mut split = s.rsplitn(2, '|');
         let last = split.next().unwrap();
-        Ok(if let Some(first) = split.next() {
-            Path::new(first.trim(), last.parse()?)
+        let (path, flags) = if let Some(first) = split.next() {
+            (first.trim(), last.parse()?)
         } else {
-            Path::new(last.trim(), PathFlags::default())
+            (last.trim(), PathFlags::default())
+        };
+        Ok(match path.strip_suffix('!') {
+            Some(path) => Path {
+                required: true,
+                ..Path::new(path.trim_end(), flags)
+            },
+            None => Path::new(path, flags),
         })
     }
 }
 
 impl<S> From<S> for Path
-    where S: Into<String> {
+    where S: Into<OsString> {
     fn from(s: S) -> Self {
         Path {
             path: s.into(),
@@ -139,23 +273,145 @@ impl Paths {
         Paths(v)
     }
 
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Path> {
+        self.0.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, Path> {
+        self.0.iter_mut()
+    }
+
+    /// Returns if an entry has the unresolved path `path`
+    pub fn contains<S: AsRef<OsStr>>(&self, path: S) -> bool {
+        self.position(path).is_some()
+    }
+
+    /// Returns the index of the first entry with the unresolved path `path`
+    pub fn position<S: AsRef<OsStr>>(&self, path: S) -> Option<usize> {
+        self.0.iter().position(|p| p.path == path.as_ref())
+    }
+
+    /// Inserts `path` in front of the first entry with the unresolved path `before`.
+    ///
+    /// Returns `false` without inserting `path` if there is no such entry.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use pathfix::config::Paths;
+    ///
+    /// let mut paths = Paths::from_path("/usr/bin:/bin");
+    /// assert!(paths.insert_before("/bin", "/usr/local/bin".into()));
+    /// assert!(!paths.insert_after("/sbin", "/usr/sbin".into()));
+    /// assert_eq!(paths.position("/usr/local/bin"), Some(1));
+    /// ```
+    pub fn insert_before<S: AsRef<OsStr>>(&mut self, before: S, path: Path) -> bool {
+        match self.position(before) {
+            Some(index) => {
+                self.0.insert(index, path);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Inserts `path` behind the first entry with the unresolved path `after`, like `insert_before`.
+    pub fn insert_after<S: AsRef<OsStr>>(&mut self, after: S, path: Path) -> bool {
+        match self.position(after) {
+            Some(index) => {
+                self.0.insert(index + 1, path);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes all entries whose unresolved path matches the glob `pattern` and returns them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use pathfix::config::Paths;
+    ///
+    /// let mut paths = Paths::from_path("/mnt/c/Windows:/usr/bin:/mnt/c/Program Files/Git/bin");
+    /// let removed = paths.remove_matching("/mnt/c/**").unwrap();
+    ///
+    /// assert_eq!(paths, Paths::from_path("/usr/bin"));
+    /// assert_eq!(removed.len(), 2);
+    /// ```
+    pub fn remove_matching(&mut self, pattern: &str) -> io::Result<Paths> {
+        let regex = Regex::new(&glob_to_regex(pattern))
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let (removed, kept) = std::mem::take(&mut self.0).into_iter()
+            .partition(|path| regex.is_match(path.path.as_bytes()));
+        self.0 = kept;
+        Ok(Paths(removed))
+    }
+
+    /// Removes entries which resolve to the same path as another entry, `~user` is looked up in `users`.
+    ///
+    /// Entries which can not be resolved are compared by their unresolved path.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use pathfix::config::{DedupMode, Paths, SystemUsers};
+    ///
+    /// let mut env = HashMap::new();
+    /// env.insert("HOME".to_string(), "/home/me".into());
+    /// let mut paths = Paths::from_path("~/bin:/usr/bin:/home/me/bin");
+    /// paths.dedup_by(DedupMode::KeepLast, &env, &SystemUsers);
+    /// assert_eq!(paths, Paths::from_path("/usr/bin:/home/me/bin"));
+    /// ```
+    pub fn dedup_by(&mut self, mode: DedupMode, env: &HashMap<String, OsString>, users: &dyn UserLookup) {
+        let keys: Vec<PathBuf> = self.0.iter()
+            .map(|path| path.resolve_with(env, users).unwrap_or_else(|| PathBuf::from(&path.path)))
+            .collect();
+        let mut duplicates = mode.duplicates(&keys).into_iter();
+        self.0.retain(|_| !duplicates.next().unwrap_or(false));
+    }
+
+    /// Keeps the entries which resolve to an existing directory in `file_system`,
+    /// `~user` is looked up in `users`
+    pub fn retain_existing(
+        &mut self,
+        env: &HashMap<String, OsString>,
+        users: &dyn UserLookup,
+        file_system: &dyn FileSystem,
+    ) {
+        self.0.retain(|path| {
+            path.resolve_with(env, users)
+                .is_some_and(|resolved| file_system.is_dir(&resolved))
+        });
+    }
+
     /// Reads PATH environment variable file and adds content to config.
     ///
-    /// The PATH environment variable will be split on ':'
+    /// The PATH environment variable will be split on ':'.
+    /// Entries don't need to be valid UTF-8.
     pub fn from_env() -> Result<Paths, VarError> {
         Ok(Paths::from_path(
-            &std::env::var("PATH")?
+            &std::env::var_os("PATH").ok_or(VarError::NotPresent)?
         ))
     }
 
     /// Parses a colon seperated path and sets that as included path.
     ///
     /// The parameter will be split on ':'
-    pub fn from_path(path: &str) -> Paths {
-        let config_source = Rc::new(ConfigSource::PathVar);
+    pub fn from_path<S: AsRef<OsStr>>(path: S) -> Paths {
+        let config_source = Arc::new(ConfigSource::PathVar);
         Paths(
-            path.split(':')
-                .map(Path::from)
+            path.as_ref().as_bytes().split(|&b| b == b':')
+                .map(|entry| Path::from(OsString::from_vec(entry.to_vec())))
                 .map(|mut path| {
                     path.source = Some(config_source.clone());
                     path
@@ -170,19 +426,70 @@ impl Paths {
         Paths(other.0.iter().chain(self.0.iter()).map(ToOwned::to_owned).collect())
     }
 
-    pub fn resolve(&self, system_flags: PathFlags, env: &HashMap<String, String>) -> Vec<String> {
-        self.0.iter()
-            .cloned()
+    /// Resolves all paths which match the `system_flags` and their condition.
+    ///
+    /// Paths with a higher priority are moved to the front,
+    /// paths with the same priority keep their order.
+    pub fn resolve(&self, system_flags: PathFlags, env: &HashMap<String, OsString>) -> Vec<PathBuf> {
+        let mut paths: Vec<&Path> = self.0.iter()
             .filter(|p| p.flags.check(system_flags))
+            .filter(|p| p.check_condition(env))
+            .collect();
+        paths.sort_by_key(|p| std::cmp::Reverse(p.priority));
+        paths.into_iter()
             .filter_map(|p| p.resolve(env))
             .collect()
     }
 
+    /// Returns all required paths, which apply to the system but are missing.
+    ///
+    /// A path is missing if it can't be resolved or `exists` returns `false` for it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use pathfix::config::{Path, Paths, PathFlags};
+    ///
+    /// let paths = Paths::new(vec!["/opt/corp/bin!".parse().unwrap(), "/usr/bin".parse().unwrap()]);
+    /// let missing = paths.missing_required(PathFlags::new(), &HashMap::new(), |_| false);
+    ///
+    /// assert_eq!(missing.len(), 1);
+    /// assert_eq!(missing[0].path.path(), "/opt/corp/bin");
+    /// ```
+    pub fn missing_required<F>(
+        &self,
+        system_flags: PathFlags,
+        env: &HashMap<String, OsString>,
+        exists: F,
+    ) -> Vec<MissingPath<'_>>
+        where F: Fn(&std::path::Path) -> bool {
+        self.missing_required_with(system_flags, env, &SystemUsers, exists)
+    }
+
+    /// Like `missing_required`, but looks up the home directories of `~user` in `users`.
+    pub fn missing_required_with<F>(
+        &self,
+        system_flags: PathFlags,
+        env: &HashMap<String, OsString>,
+        users: &dyn UserLookup,
+        exists: F,
+    ) -> Vec<MissingPath<'_>>
+        where F: Fn(&std::path::Path) -> bool {
+        self.0.iter()
+            .filter(|p| p.required)
+            .filter(|p| p.flags.check(system_flags))
+            .filter(|p| p.check_condition(env))
+            .map(|p| MissingPath { path: p, resolved: p.resolve_with(env, users) })
+            .filter(|missing| missing.resolved.as_deref().map(|p| !exists(p)).unwrap_or(true))
+            .collect()
+    }
+
     /// Sets the source of all paths in the internal vector.
     pub fn set_source(&mut self, source: ConfigSource) {
-        let rc = Rc::new(source);
+        let source = Arc::new(source);
         for path in self.0.iter_mut() {
-            path.source = Some(rc.clone());
+            path.source = Some(source.clone());
         }
     }
 
@@ -190,35 +497,215 @@ impl Paths {
     pub fn normalize(self) -> Paths {
         Paths::new(self.0.into_iter().map(Path::normalize).collect())
     }
+
+    /// Returns the most compact style which can represent all paths
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use pathfix::config::{Paths, PathsStyle};
+    ///
+    /// let paths: Paths = vec!["/foo/bar"].into();
+    /// assert_eq!(paths.style(), PathsStyle::Map);
+    /// ```
+    pub fn style(&self) -> PathsStyle {
+        if self.0.iter().all(Path::is_simple) {
+            PathsStyle::Map
+        } else {
+            PathsStyle::Tables
+        }
+    }
+
+    /// Returns a wrapper, which serializes the paths in the given style.
+    ///
+    /// Serializing paths with attributes other than flags in the `Map` style fails.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use pathfix::config::{Paths, PathsStyle};
+    ///
+    /// let paths: Paths = vec!["/foo/bar"].into();
+    /// let toml = toml::to_string(&paths.with_style(PathsStyle::Tables)).unwrap();
+    /// ```
+    pub fn with_style(&self, style: PathsStyle) -> StyledPaths<'_> {
+        StyledPaths {
+            paths: self,
+            style,
+        }
+    }
 }
 
-impl Serialize for Paths {
+impl IntoIterator for Paths {
+    type Item = Path;
+    type IntoIter = std::vec::IntoIter<Path>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Paths {
+    type Item = &'a Path;
+    type IntoIter = std::slice::Iter<'a, Path>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+impl FromIterator<Path> for Paths {
+    fn from_iter<I: IntoIterator<Item = Path>>(iter: I) -> Self {
+        Paths(iter.into_iter().collect())
+    }
+}
+
+impl Extend<Path> for Paths {
+    fn extend<I: IntoIterator<Item = Path>>(&mut self, iter: I) {
+        self.0.extend(iter)
+    }
+}
+
+/// Handling of entries which refer to the same directory
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DedupMode {
+    /// Keeps all occurrences
+    Keep,
+    /// Keeps the first occurrence
+    KeepFirst,
+    /// Keeps the last occurrence
+    KeepLast,
+}
+
+impl DedupMode {
+    /// Returns for each key if it is a duplicate which is removed
+    pub(crate) fn duplicates<T: Eq + Hash>(self, keys: &[T]) -> Vec<bool> {
+        let mut seen = HashSet::new();
+        match self {
+            DedupMode::Keep => vec![false; keys.len()],
+            DedupMode::KeepFirst => keys.iter().map(|key| !seen.insert(key)).collect(),
+            DedupMode::KeepLast => {
+                let mut duplicates: Vec<bool> = keys.iter().rev().map(|key| !seen.insert(key)).collect();
+                duplicates.reverse();
+                duplicates
+            }
+        }
+    }
+}
+
+/// A required `Path` which is missing on the system
+///
+/// `resolved` is `None` if the path could not be resolved.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct MissingPath<'a> {
+    pub path: &'a Path,
+    pub resolved: Option<PathBuf>,
+}
+
+impl Display for MissingPath<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.resolved {
+            Some(resolved) => write!(f, "required path {} does not exist", resolved.to_string_lossy())?,
+            None => write!(f, "required path {} can not be resolved", self.path.path().to_string_lossy())?,
+        }
+        if let Some(source) = self.path.source() {
+            write!(f, " ({})", source)?;
+        }
+        Ok(())
+    }
+}
+
+/// Syntax in which `Paths` are written in a configuration file
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PathsStyle {
+    /// Map from the path to it's flags: `"/usr/bin" = "unix"`
+    Map,
+    /// Array of tables: `[[paths]]` with a `path` key and optional attributes
+    Tables,
+}
+
+/// `Paths` which will be serialized in a specific `PathsStyle`
+///
+/// Use `Paths::with_style` to create it.
+#[derive(Debug, Copy, Clone)]
+pub struct StyledPaths<'a> {
+    paths: &'a Paths,
+    style: PathsStyle,
+}
+
+impl Serialize for StyledPaths<'_> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where
         S: Serializer,
     {
-        let mut serialize_map = serializer.serialize_map(Some(self.0.len()))?;
-        for path in &self.0 {
-            serialize_map.serialize_entry(&path.path, &path.flags)?;
+        match self.style {
+            PathsStyle::Map => {
+                let mut serialize_map = serializer.serialize_map(Some(self.paths.0.len()))?;
+                for path in &self.paths.0 {
+                    if !path.is_simple() {
+                        return Err(S::Error::custom(format!(
+                            "path {} has attributes which can not be written as map",
+                            path.path.to_string_lossy()
+                        )));
+                    }
+                    let key = path.path.to_str().ok_or_else(|| S::Error::custom(format!(
+                        "path {} is not valid UTF-8", path.path.to_string_lossy()
+                    )))?;
+                    serialize_map.serialize_entry(key, &path.flags)?;
+                }
+                serialize_map.end()
+            }
+            PathsStyle::Tables => {
+                let mut serialize_seq = serializer.serialize_seq(Some(self.paths.0.len()))?;
+                for path in &self.paths.0 {
+                    serialize_seq.serialize_element(path)?;
+                }
+                serialize_seq.end()
+            }
         }
-        serialize_map.end()
     }
 }
 
+impl Serialize for Paths {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where
+        S: Serializer,
+    {
+        self.with_style(self.style()).serialize(serializer)
+    }
+}
+
+/// Element of the array syntax, which is either a plain path or a table with attributes
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PathEntry {
+    Plain(String),
+    Table(Path),
+}
+
 struct PathsVisitor;
 
 impl<'de> Visitor<'de> for PathsVisitor {
     type Value = Paths;
 
     fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-        formatter.write_str("an map that maps paths to their flags as strings")
+        formatter.write_str("an map that maps paths to their flags as strings or an array of path tables")
     }
 
     fn visit_map<A>(self, mut map: A) -> Result<Paths, A::Error> where
         A: MapAccess<'de>, {
         let mut paths = Vec::new();
-        while let Some((path, flags)) = map.next_entry()? {
-            paths.push(Path {
-                path, flags, ..Default::default()
+        while let Some((path, flags)) = map.next_entry::<String, PathFlags>()? {
+            paths.push(Path::new(path, flags))
+        }
+        Ok(Paths(paths))
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Paths, A::Error> where
+        A: SeqAccess<'de>, {
+        let mut paths = Vec::new();
+        while let Some(entry) = seq.next_element()? {
+            paths.push(match entry {
+                PathEntry::Plain(path) => Path::from(path),
+                PathEntry::Table(path) => path,
             })
         }
         Ok(Paths(paths))
@@ -228,7 +715,7 @@ impl<'de> Visitor<'de> for PathsVisitor {
 impl<'de> Deserialize<'de> for Paths {
     fn deserialize<D>(deserializer: D) -> Result<Paths, D::Error> where
         D: Deserializer<'de> {
-        deserializer.deserialize_map(PathsVisitor)
+        deserializer.deserialize_any(PathsVisitor)
     }
 }
 
@@ -247,11 +734,19 @@ impl<T> From<Vec<T>> for Paths
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
+    use std::ffi::OsString;
+    use std::path::PathBuf;
     use std::string::ToString;
-    use std::rc::Rc;
+    use std::sync::Arc;
 
-    use crate::config::{Path, Paths, PathFlags, ConfigSource};
+    use crate::config::{Path, Paths, PathFlags, PathsStyle, ConfigSource, DedupMode, MemoryFileSystem, PasswdFiles};
     use std::io;
+    use serde::{Serialize, Deserialize};
+
+    #[derive(Serialize, Deserialize)]
+    struct TestType {
+        pub paths: Paths,
+    }
 
     #[test]
     fn test_parse_path() {
@@ -262,6 +757,10 @@ mod tests {
                 "  /foo/bar|windows    |  admin  ",
                 Path::new("/foo/bar|windows", "admin".parse().unwrap())
             ),
+            (
+                "/opt/corp/bin !  |unix",
+                Path { required: true, ..Path::new("/opt/corp/bin", "unix".parse().unwrap()) }
+            ),
         ];
         for (s, wanted) in &cases {
             let path: Path = s.parse().unwrap();
@@ -277,29 +776,70 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_display_roundtrip() {
+        for s in &["/foo/bar", "/foo/bar|admin,any", "/opt/corp/bin!|unix", "~/bin!"] {
+            let path: Path = s.parse().unwrap();
+            assert_eq!(&path.to_string(), s);
+        }
+    }
+
+    #[test]
+    fn test_missing_required() {
+        let mut paths = Paths::new(vec![
+            "/exists!".parse().unwrap(),
+            "/missing!".parse().unwrap(),
+            "$UNKNOWN/bin!".parse().unwrap(),
+            "/windows-only!|windows".parse().unwrap(),
+            "/not-required".parse().unwrap(),
+        ]);
+        paths.set_source(ConfigSource::Included);
+        let missing: Vec<String> = paths
+            .missing_required("linux".parse().unwrap(), &HashMap::new(), |p| p == "/exists")
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(missing, vec![
+            "required path /missing does not exist (included in binary)",
+            "required path $UNKNOWN/bin can not be resolved (included in binary)",
+        ]);
+    }
+
     #[test]
     fn test_resolve() {
-        let env: HashMap<String, String> = [("HOME", "/home/user"), ("FOO", "/foobar")]
+        let env: HashMap<String, OsString> = [("HOME", "/home/user"), ("FOO", "/foobar")]
             .iter()
-            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .map(|(k, v)| (k.to_string(), OsString::from(v)))
             .collect();
         let testvec = [
             (
                 Path::from("/fnort/bar"),
-                Some("/fnort/bar".to_string())
+                Some(PathBuf::from("/fnort/bar"))
             ),
             (
                 Path::from("$HOME/foo"),
-                Some("/home/user/foo".to_string())
+                Some(PathBuf::from("/home/user/foo"))
             ),
             (
                 Path::from("~/foo"),
-                Some("/home/user/foo".to_string())
+                Some(PathBuf::from("/home/user/foo"))
             ),
             (
                 Path::from("$UNKOWN/foo"),
                 None
             ),
+            (
+                Path::from("${FOO}/bin"),
+                Some(PathBuf::from("/foobar/bin"))
+            ),
+            (
+                Path::from("${UNKNOWN:-/opt}/bin"),
+                Some(PathBuf::from("/opt/bin"))
+            ),
+            (
+                Path::from("${UNKNOWN:?not set}/bin"),
+                None
+            ),
         ];
 
         for (path, wanted) in &testvec {
@@ -320,7 +860,7 @@ mod tests {
 
     #[test]
     fn test_from_path() {
-        let source = Rc::new(ConfigSource::PathVar);
+        let source = Arc::new(ConfigSource::PathVar);
         let test_vec: Vec<(&'static str, Paths)> = vec![
             (
                 "/foo/bar",
@@ -359,4 +899,116 @@ mod tests {
             assert_eq!(a.merge(b), out)
         }
     }
+
+    #[test]
+    fn test_deserialize_tables() {
+        let input: TestType = toml::from_str(r#"
+            [[paths]]
+            path = "/foo/bar"
+
+            [[paths]]
+            path = "/opt/corp/bin"
+            flags = "unix,admin"
+            comment = "corporate toolchain"
+            required = true
+            priority = 10
+            condition = "CORP"
+        "#).unwrap();
+        let wanted = Paths::new(vec![
+            Path::new("/foo/bar", PathFlags::new()),
+            Path {
+                comment: Some("corporate toolchain".to_string()),
+                required: true,
+                priority: 10,
+                condition: Some("CORP".to_string()),
+                ..Path::new("/opt/corp/bin", "unix,admin".parse().unwrap())
+            },
+        ]);
+        assert_eq!(input.paths, wanted);
+    }
+
+    #[test]
+    fn test_deserialize_plain_array() {
+        let input: TestType = toml::from_str(r#"paths = [ "~/mybin", { path = "/usr/bin", flags = "unix" } ]"#).unwrap();
+        let wanted = Paths::new(vec![
+            Path::from("~/mybin"),
+            Path::new("/usr/bin", "unix".parse().unwrap()),
+        ]);
+        assert_eq!(input.paths, wanted);
+    }
+
+    #[test]
+    fn test_serialize_styles() {
+        let simple = TestType { paths: vec!["/foo/bar"].into() };
+        assert_eq!(simple.paths.style(), PathsStyle::Map);
+        assert_eq!(toml::to_string(&simple).unwrap(), "[paths]\n\"/foo/bar\" = \"any\"\n");
+
+        let tables = TestType {
+            paths: Paths::new(vec![Path { required: true, ..Path::from("/foo/bar") }]),
+        };
+        assert_eq!(tables.paths.style(), PathsStyle::Tables);
+        let serialized = toml::to_string(&tables).unwrap();
+        assert_eq!(serialized, "[[paths]]\npath = \"/foo/bar\"\nflags = \"any\"\nrequired = true\n");
+        let deserialized: TestType = toml::from_str(&serialized).unwrap();
+        assert_eq!(deserialized.paths, tables.paths);
+
+        assert!(toml::to_string(&tables.paths.with_style(PathsStyle::Map)).is_err());
+    }
+
+    #[test]
+    fn test_resolve_priority_and_condition() {
+        let env: HashMap<String, OsString> = [("CORP", "1")]
+            .iter()
+            .map(|(k, v)| (k.to_string(), OsString::from(v)))
+            .collect();
+        let paths = Paths::new(vec![
+            Path::from("/a"),
+            Path { priority: 5, ..Path::from("/b") },
+            Path { condition: Some("MISSING".to_string()), ..Path::from("/c") },
+            Path { condition: Some("CORP".to_string()), ..Path::from("/d") },
+        ]);
+        assert_eq!(paths.resolve(PathFlags::default(), &env), vec![PathBuf::from("/b"), PathBuf::from("/a"), PathBuf::from("/d")]);
+    }
+
+    #[test]
+    fn test_dedup_by() {
+        let users = PasswdFiles::parse("alice:x:1000:1000::/home/alice:/bin/sh\n", "");
+        let env: HashMap<String, OsString> = vec![("HOME".to_string(), "/home/alice".into())].into_iter().collect();
+        let dedup = |mode: DedupMode| {
+            let mut paths = Paths::from_path("~/bin:/usr/bin:/home/alice/bin:$HOME/bin:~alice/bin:$UNSET/bin:$UNSET/bin");
+            paths.dedup_by(mode, &env, &users);
+            paths.iter().map(|path| path.path().to_string_lossy().into_owned()).collect::<Vec<_>>()
+        };
+        assert_eq!(dedup(DedupMode::Keep).len(), 7);
+        assert_eq!(dedup(DedupMode::KeepFirst), vec!["~/bin", "/usr/bin", "$UNSET/bin"]);
+        assert_eq!(dedup(DedupMode::KeepLast), vec!["/usr/bin", "~alice/bin", "$UNSET/bin"]);
+    }
+
+    #[test]
+    fn test_collection() {
+        let mut paths: Paths = vec!["/usr/bin", "~/bin", "/usr/bin"].into_iter().map(Path::from).collect();
+        paths.extend(vec![Path::from("/opt/bin")]);
+        assert_eq!(paths.len(), 4);
+        assert!(paths.contains("~/bin"));
+        assert!(paths.insert_after("~/bin", Path::from("~/.local/bin")));
+        assert!(!paths.insert_before("/sbin", Path::from("/usr/sbin")));
+
+        let users = PasswdFiles::parse("alice:x:1000:1000::/srv/alice:/bin/sh\n", "");
+        let env: HashMap<String, OsString> = vec![("HOME".to_string(), "/home/user".into())].into_iter().collect();
+        paths.dedup_by(DedupMode::KeepFirst, &env, &users);
+        let names: Vec<_> = paths.iter().map(|path| path.path().to_string_lossy().into_owned()).collect();
+        assert_eq!(names, vec!["/usr/bin", "~/bin", "~/.local/bin", "/opt/bin"]);
+
+        let file_system = MemoryFileSystem::new()
+            .with_dir("/home/user/bin")
+            .with_dir("/srv/alice/bin")
+            .with_dir("/usr/bin");
+        paths.extend(vec![Path::from("~alice/bin"), Path::from("~bob/bin")]);
+        paths.retain_existing(&env, &users, &file_system);
+        let names: Vec<_> = (&paths).into_iter().map(|path| path.path().to_string_lossy().into_owned()).collect();
+        assert_eq!(names, vec!["/usr/bin", "~/bin", "~alice/bin"]);
+
+        assert_eq!(paths.remove_matching("~/*").unwrap().len(), 1);
+        assert_eq!(paths.into_iter().count(), 2);
+    }
 }
@@ -2,10 +2,11 @@ use std::fmt::Display;
 use core::fmt;
 use std::str::FromStr;
 use std::io;
-use crate::config::IncludeAdministrative;
+use crate::config::{FileSystem, IncludeAdministrative, RealFileSystem, SystemProfile};
 use serde::{Serialize, Serializer, Deserialize, Deserializer, de};
 use serde::de::Visitor;
 use std::borrow::Borrow;
+use std::path::Path;
 use thiserror::Error;
 
 
@@ -43,6 +44,24 @@ impl PathFlags {
         Default::default()
     }
 
+    /// Sets if the flags require, or the system has administrative rights
+    pub fn with_admin(self, admin: bool) -> PathFlags {
+        PathFlags { admin, ..self }
+    }
+
+    /// Sets the required operating system, or the operating system of the system
+    pub fn with_os(self, os: PathOs) -> PathFlags {
+        PathFlags { os, ..self }
+    }
+
+    pub fn admin(self) -> bool {
+        self.admin
+    }
+
+    pub fn os(self) -> PathOs {
+        self.os
+    }
+
     /// Checks if the `PathFlags` are given for the flags of the given system.
     ///
     /// # Example
@@ -76,10 +95,18 @@ impl PathFlags {
     /// ```
     pub fn this_system<I>(include_administrative: &I) -> PathFlags
         where I: Borrow<IncludeAdministrative> {
-        PathFlags {
-            admin: include_administrative.borrow().check_current_user().unwrap_or(true),
-            os: PathOs::this_system(),
-        }
+        SystemProfile::this_system().path_flags(include_administrative.borrow())
+    }
+
+    /// Get given `PathFlags` for a root file system, e.g. of a container image.
+    ///
+    /// The operating system and the groups of the current user are read from `root`.
+    pub fn for_root<I, P>(include_administrative: &I, root: P) -> PathFlags
+        where I: Borrow<IncludeAdministrative>,
+              P: AsRef<Path> {
+        SystemProfile::this_system()
+            .with_root(root.as_ref())
+            .path_flags(include_administrative.borrow())
     }
 }
 
@@ -114,7 +141,7 @@ impl<'de> Visitor<'de> for PathFlagsVisitor {
     fn visit_str<E>(self, v: &str) -> Result<Self::Value, E> where
         E: de::Error, {
         PathFlags::from_str(v).map_err(|e| E::custom(
-            format!("error parsing path flags: {}", e.to_string())
+            format!("error parsing path flags: {}", e)
         ))
     }
 }
@@ -184,8 +211,9 @@ impl FromStr for PathFlags {
 /// assert_eq!(windows.is(unix).unwrap(), false);
 /// assert_eq!(windows.is(any).unwrap(), true);
 /// ```
-#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
+#[derive(Debug, Default, Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
 pub enum PathOs {
+    #[default]
     Any,
     Unix,
     Windows,
@@ -211,6 +239,40 @@ impl PathOs {
         }
     }
 
+    /// Detects the operating system of a root file system, e.g. of a container image.
+    ///
+    /// Systems are recognized by their `os-release` file or the macOS system version file.
+    /// If neither exists, the current operating system is assumed.
+    pub fn from_root<P: AsRef<Path>>(root: P) -> PathOs {
+        PathOs::from_root_with(root, &RealFileSystem)
+    }
+
+    /// Like `from_root`, but reads the root file system from `file_system`.
+    pub fn from_root_with<P: AsRef<Path>>(root: P, file_system: &dyn FileSystem) -> PathOs {
+        let root = root.as_ref();
+        for os_release in &["etc/os-release", "usr/lib/os-release"] {
+            if let Ok(contents) = file_system.read_to_string(&root.join(os_release)) {
+                return PathOs::from_os_release(&contents);
+            }
+        }
+        if file_system.exists(&root.join("System/Library/CoreServices/SystemVersion.plist")) {
+            return PathOs::MacOs;
+        }
+        PathOs::this_system()
+    }
+
+    /// Returns the operating system described by the contents of an `os-release` file
+    fn from_os_release(contents: &str) -> PathOs {
+        let id = contents.lines()
+            .filter_map(|line| line.strip_prefix("ID="))
+            .map(|id| id.trim().trim_matches(|c| c == '"' || c == '\''))
+            .next();
+        match id {
+            Some("freebsd") | Some("netbsd") | Some("openbsd") | Some("dragonfly") => PathOs::Unix,
+            _ => PathOs::Linux,
+        }
+    }
+
     /// Checks if `self` is the specified operating system or belongs
     /// to the specified operating system group
     pub fn is(self, other: PathOs) -> PathOsResult<bool> {
@@ -245,12 +307,6 @@ impl PathOs {
     }
 }
 
-impl Default for PathOs {
-    fn default() -> PathOs {
-        PathOs::Any
-    }
-}
-
 impl fmt::Display for PathOs {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", match self {
@@ -324,7 +380,7 @@ mod tests {
 
     use std::str::FromStr;
 
-    use crate::config::PathFlags;
+    use crate::config::{MemoryFileSystem, PathFlags, PathOs};
 
     #[derive(Serialize, Deserialize)]
     struct TestType {
@@ -344,4 +400,21 @@ mod tests {
         let wanted: PathFlags = "admin,macos".parse().unwrap();
         assert_eq!(input.flags, wanted);
     }
+
+    #[test]
+    fn test_from_os_release() {
+        assert_eq!(PathOs::from_os_release("NAME=\"Debian GNU/Linux\"\nID=debian\n"), PathOs::Linux);
+        assert_eq!(PathOs::from_os_release("NAME=FreeBSD\nID=\"freebsd\"\n"), PathOs::Unix);
+        assert_eq!(PathOs::from_os_release(""), PathOs::Linux);
+    }
+
+    #[test]
+    fn test_from_root() {
+        let alpine = MemoryFileSystem::new().with_file("/srv/alpine/etc/os-release", "ID=alpine\n");
+        assert_eq!(PathOs::from_root_with("/srv/alpine", &alpine), PathOs::Linux);
+        let freebsd = MemoryFileSystem::new().with_file("/srv/freebsd/usr/lib/os-release", "ID=freebsd\n");
+        assert_eq!(PathOs::from_root_with("/srv/freebsd", &freebsd), PathOs::Unix);
+        let macos = MemoryFileSystem::new().with_file("/System/Library/CoreServices/SystemVersion.plist", "");
+        assert_eq!(PathOs::from_root_with("/", &macos), PathOs::MacOs);
+    }
 }
@@ -0,0 +1,318 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::command::cache_dir;
+
+/// Number of results which are kept, e.g. for different options or users
+const MAX_ENTRIES: usize = 16;
+
+/// Identifies a pathfix invocation before any configuration is read
+///
+/// The key covers the command line, the user and group identity, the pathfix binary
+/// and the configuration files.
+#[derive(Debug, Clone)]
+pub struct ResultKey {
+    hasher: DefaultHasher,
+}
+
+impl ResultKey {
+    /// Creates a key for the command line `args` and the identity of the current user
+    pub fn new<S: AsRef<str>>(args: &[S]) -> ResultKey {
+        let mut hasher = DefaultHasher::new();
+        for arg in args {
+            arg.as_ref().hash(&mut hasher);
+        }
+        users::get_current_uid().hash(&mut hasher);
+        users::get_current_gid().hash(&mut hasher);
+        if let Ok(groups) = users::group_access_list() {
+            for group in groups {
+                group.gid().hash(&mut hasher);
+            }
+        }
+        if let Ok(exe) = std::env::current_exe() {
+            stamp(&exe).hash(&mut hasher);
+        }
+        ResultKey { hasher }
+    }
+
+    /// Adds the modification time and size of a configuration file, which may be missing
+    pub fn with_file<P: AsRef<Path>>(mut self, path: P) -> ResultKey {
+        path.as_ref().hash(&mut self.hasher);
+        stamp(path.as_ref()).hash(&mut self.hasher);
+        self
+    }
+
+    /// Adds the modification time of a directory, which may be missing, and of all files in it
+    pub fn with_dir<P: AsRef<Path>>(mut self, path: P) -> ResultKey {
+        let path = path.as_ref();
+        path.hash(&mut self.hasher);
+        stamp(path).hash(&mut self.hasher);
+        let mut files: Vec<PathBuf> = fs::read_dir(path).into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .collect();
+        files.sort();
+        for file in files {
+            file.hash(&mut self.hasher);
+            stamp(&file).hash(&mut self.hasher);
+        }
+        self
+    }
+
+    fn fingerprint(&self) -> String {
+        format!("{:016x}", self.hasher.finish())
+    }
+}
+
+/// Output of a previous run
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct CachedResult {
+    pub output: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+/// Cached results of whole pathfix runs
+///
+/// A result is only used if the `ResultKey`, the environment variables the configuration
+/// refers to and the modification times of the parent directories of all candidate paths
+/// did not change.
+#[derive(Debug, Default)]
+pub struct ResultCache {
+    location: Option<PathBuf>,
+    entries: Vec<ResultEntry>,
+    changed: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
+struct ResultEntry {
+    key: String,
+    env_names: Vec<String>,
+    env: String,
+    result: CachedResult,
+    dirs: Vec<DirStamp>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
+struct DirStamp {
+    path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mtime: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CacheFile {
+    #[serde(default)]
+    result: Vec<ResultEntry>,
+}
+
+impl ResultCache {
+    /// Creates a cache which does not store anything
+    pub fn disabled() -> ResultCache {
+        Default::default()
+    }
+
+    /// Opens the cache file at `location`.
+    ///
+    /// A missing or broken cache file results in an empty cache.
+    pub fn open<P: Into<PathBuf>>(location: P) -> ResultCache {
+        let location = location.into();
+        let entries = fs::read(&location).ok()
+            .and_then(|contents| toml::from_slice::<CacheFile>(&contents).ok())
+            .map(|file| file.result)
+            .unwrap_or_default();
+        ResultCache {
+            location: Some(location),
+            entries,
+            changed: false,
+        }
+    }
+
+    /// Returns the default cache file location in `$XDG_CACHE_HOME/pathfix` or `~/.cache/pathfix`.
+    pub fn default_location() -> Option<PathBuf> {
+        cache_dir().map(|dir| dir.join("results.toml"))
+    }
+
+    /// Returns the result stored for `key`, if it is still valid in the environment `env`.
+    pub fn get(&self, key: &ResultKey, env: &HashMap<String, OsString>) -> Option<&CachedResult> {
+        let fingerprint = key.fingerprint();
+        let entry = self.entries.iter().find(|entry| entry.key == fingerprint)?;
+        let valid = entry.env == hash_env(&entry.env_names, env)
+            && entry.dirs.iter().all(|dir| stamp(Path::new(&dir.path)) == dir.mtime);
+        if valid {
+            Some(&entry.result)
+        } else {
+            None
+        }
+    }
+
+    /// Stores the `result` of a run.
+    ///
+    /// `env_names` are the variables the configuration refers to and `candidates` all
+    /// paths whose existence was checked. Results with relative candidates or
+    /// candidates which are not valid UTF-8 are not stored.
+    pub fn insert(
+        &mut self,
+        key: &ResultKey,
+        env_names: Vec<String>,
+        env: &HashMap<String, OsString>,
+        candidates: &[PathBuf],
+        result: CachedResult,
+    ) {
+        if self.location.is_none() {
+            return;
+        }
+        let mut parents: Vec<&Path> = Vec::new();
+        for candidate in candidates {
+            if candidate.is_relative() {
+                return;
+            }
+            if let Some(parent) = candidate.parent() {
+                if !parents.contains(&parent) {
+                    parents.push(parent);
+                }
+            }
+        }
+        let dirs = parents.into_iter()
+            .map(|parent| parent.to_str().map(|path| DirStamp {
+                path: path.to_string(),
+                mtime: stamp(parent),
+            }))
+            .collect::<Option<Vec<_>>>();
+        let dirs = match dirs {
+            Some(dirs) => dirs,
+            None => return,
+        };
+
+        let entry = ResultEntry {
+            key: key.fingerprint(),
+            env: hash_env(&env_names, env),
+            env_names,
+            result,
+            dirs,
+        };
+        self.entries.retain(|e| e.key != entry.key);
+        self.entries.push(entry);
+        if self.entries.len() > MAX_ENTRIES {
+            self.entries.remove(0);
+        }
+        self.changed = true;
+    }
+
+    /// Writes the cache back to its file, if it was changed.
+    pub fn save(&self) -> io::Result<()> {
+        let location = match &self.location {
+            Some(location) if self.changed => location,
+            _ => return Ok(()),
+        };
+        if let Some(parent) = location.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let contents = toml::to_string(&CacheFile { result: self.entries.clone() })
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let tmp = location.with_extension("tmp");
+        io::Write::write_all(&mut File::create(&tmp)?, contents.as_bytes())?;
+        fs::rename(tmp, location)
+    }
+}
+
+/// Removes all cached results and command outputs.
+pub fn clear_cache() -> io::Result<()> {
+    let dir = match cache_dir() {
+        Some(dir) => dir,
+        None => return Ok(()),
+    };
+    match fs::remove_dir_all(dir) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// Returns the modification time and size of a file, or `None` if it does not exist.
+fn stamp(path: &Path) -> Option<String> {
+    let metadata = fs::metadata(path).ok()?;
+    let mtime = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some(format!("{}.{}", mtime.as_nanos(), metadata.len()))
+}
+
+fn hash_env(names: &[String], env: &HashMap<String, OsString>) -> String {
+    let mut hasher = DefaultHasher::new();
+    for name in names {
+        name.hash(&mut hasher);
+        env.get(name).hash(&mut hasher);
+    }
+    format!("{:016x}", hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::ffi::OsString;
+    use std::path::PathBuf;
+
+    use crate::config::{CachedResult, ResultCache, ResultKey};
+
+    fn env(home: &str) -> HashMap<String, OsString> {
+        [("HOME", home), ("SHLVL", "1")]
+            .iter()
+            .map(|(k, v)| (k.to_string(), OsString::from(v)))
+            .collect()
+    }
+
+    #[test]
+    fn test_cache() {
+        let dir = std::env::temp_dir().join(format!("pathfix-test-results-{}", std::process::id()));
+        let location = dir.join("results.toml");
+        let _ = std::fs::remove_dir_all(&dir);
+        let candidates = vec![dir.join("sdk").join("bin")];
+        std::fs::create_dir_all(&candidates[0]).unwrap();
+
+        let key = ResultKey::new(&["-D"]).with_file(dir.join("pathfix.toml"));
+        let result = CachedResult { output: "/usr/bin\n".to_string(), warnings: vec![] };
+        let mut cache = ResultCache::open(&location);
+        assert_eq!(cache.get(&key, &env("/home/user")), None);
+        cache.insert(&key, vec!["HOME".to_string()], &env("/home/user"), &candidates, result.clone());
+        cache.save().unwrap();
+
+        let cache = ResultCache::open(&location);
+        assert_eq!(cache.get(&key, &env("/home/user")), Some(&result));
+        assert_eq!(cache.get(&ResultKey::new(&["-e"]), &env("/home/user")), None);
+        assert_eq!(cache.get(&key, &env("/home/other")), None);
+
+        std::fs::write(dir.join("pathfix.toml"), "").unwrap();
+        let changed_key = ResultKey::new(&["-D"]).with_file(dir.join("pathfix.toml"));
+        assert_eq!(cache.get(&changed_key, &env("/home/user")), None);
+
+        std::fs::remove_dir(&candidates[0]).unwrap();
+        assert_eq!(cache.get(&key, &env("/home/user")), None);
+
+        let dir_key = || ResultKey::new(&["-D"]).with_dir(dir.join("paths.d"));
+        let missing = dir_key().fingerprint();
+        std::fs::create_dir(dir.join("paths.d")).unwrap();
+        let empty = dir_key().fingerprint();
+        std::fs::write(dir.join("paths.d/sdk"), "/opt/sdk/bin\n").unwrap();
+        let added = dir_key().fingerprint();
+        std::fs::write(dir.join("paths.d/sdk"), "/opt/sdk/bin\n/opt/sdk/sbin\n").unwrap();
+        assert_ne!(missing, empty);
+        assert_ne!(empty, added);
+        assert_ne!(added, dir_key().fingerprint());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_relative_candidates() {
+        let mut cache = ResultCache::open("/nonexistent/results.toml");
+        let key = ResultKey::new(&["-D"]);
+        cache.insert(&key, vec![], &env("/"), &[PathBuf::from("./bin")], CachedResult::default());
+        assert_eq!(cache.get(&key, &env("/")), None);
+    }
+}
@@ -0,0 +1,274 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::config::{
+    FileSystem, IncludeAdministrative, PasswdFiles, PathFlags, PathOs, RealFileSystem, SystemUsers,
+    UserLookup,
+};
+
+/// Description of the system for which paths are evaluated
+///
+/// By default this is the running system and the current user.
+/// The operating system, the user and the administrative rights can be overridden
+/// to preview the paths of another system, and existence checks can be done below
+/// another root directory or be skipped.
+///
+/// Files are read from a `FileSystem` and users from a `UserLookup`, which can be replaced
+/// to describe another machine. Below a root directory, users and groups are read from
+/// the files of the root instead.
+///
+/// # Examples
+///
+/// ```
+/// use pathfix::config::{IncludeAdministrative, PathFlags, PathOs, SystemProfile};
+///
+/// let profile = SystemProfile::this_system()
+///     .with_os(PathOs::MacOs)
+///     .with_admin(false)
+///     .without_fs_check();
+/// let flags = profile.path_flags(&IncludeAdministrative::Always);
+///
+/// assert!("macos".parse::<PathFlags>().unwrap().check(flags));
+/// assert!(!"admin".parse::<PathFlags>().unwrap().check(flags));
+/// assert!(profile.exists(std::path::Path::new("/this/does/not/exist")));
+/// ```
+#[derive(Debug, Clone)]
+pub struct SystemProfile {
+    os: Option<PathOs>,
+    user: Option<String>,
+    admin: Option<bool>,
+    root: Option<PathBuf>,
+    fs_check: bool,
+    file_system: Arc<dyn FileSystem>,
+    user_lookup: Arc<dyn UserLookup>,
+}
+
+impl SystemProfile {
+    /// Creates the profile of the running system and the current user
+    pub fn this_system() -> SystemProfile {
+        SystemProfile {
+            os: None,
+            user: None,
+            admin: None,
+            root: None,
+            fs_check: true,
+            file_system: Arc::new(RealFileSystem),
+            user_lookup: Arc::new(SystemUsers),
+        }
+    }
+
+    /// Evaluates paths for another operating system
+    pub fn with_os(self, os: PathOs) -> SystemProfile {
+        SystemProfile {
+            os: Some(os),
+            ..self
+        }
+    }
+
+    /// Evaluates the administrative rights and the home directory of another user
+    pub fn with_user<S: Into<String>>(self, user: S) -> SystemProfile {
+        SystemProfile {
+            user: Some(user.into()),
+            ..self
+        }
+    }
+
+    /// Sets the administrative rights instead of evaluating `IncludeAdministrative`
+    pub fn with_admin(self, admin: bool) -> SystemProfile {
+        SystemProfile {
+            admin: Some(admin),
+            ..self
+        }
+    }
+
+    /// Checks paths below `root`, from which the operating system and users are read as well
+    pub fn with_root<P: Into<PathBuf>>(self, root: P) -> SystemProfile {
+        SystemProfile {
+            root: Some(root.into()),
+            ..self
+        }
+    }
+
+    /// Assumes that all paths exist
+    pub fn without_fs_check(self) -> SystemProfile {
+        SystemProfile {
+            fs_check: false,
+            ..self
+        }
+    }
+
+    /// Reads files and checks paths in `file_system` instead of the real file system
+    pub fn with_file_system<F: FileSystem + 'static>(self, file_system: F) -> SystemProfile {
+        SystemProfile {
+            file_system: Arc::new(file_system),
+            ..self
+        }
+    }
+
+    /// Looks up users and groups in `user_lookup` instead of the running system, if no root is set
+    pub fn with_user_lookup<U: UserLookup + 'static>(self, user_lookup: U) -> SystemProfile {
+        SystemProfile {
+            user_lookup: Arc::new(user_lookup),
+            ..self
+        }
+    }
+
+    /// Returns the operating system, which is detected in the file system if it is not set
+    pub fn os(&self) -> PathOs {
+        match self.os {
+            Some(os) => os,
+            None => PathOs::from_root_with(self.root.as_deref().unwrap_or_else(|| Path::new("/")), self.file_system()),
+        }
+    }
+
+    /// Returns the user, or `None` for the current user
+    pub fn user(&self) -> Option<&str> {
+        self.user.as_deref()
+    }
+
+    pub fn root(&self) -> Option<&Path> {
+        self.root.as_deref()
+    }
+
+    pub fn fs_check(&self) -> bool {
+        self.fs_check
+    }
+
+    pub fn file_system(&self) -> &dyn FileSystem {
+        self.file_system.as_ref()
+    }
+
+    pub fn user_lookup(&self) -> &dyn UserLookup {
+        self.user_lookup.as_ref()
+    }
+
+    /// Returns the users of the system, which are read from `etc/passwd` and `etc/group`
+    /// below the root if it is set.
+    pub fn users(&self) -> io::Result<Arc<dyn UserLookup>> {
+        match &self.root {
+            Some(root) => Ok(Arc::new(PasswdFiles::in_root_with(root, self.file_system())?)),
+            None => Ok(self.user_lookup.clone()),
+        }
+    }
+
+    /// Returns the home directory of the user from the passwd database,
+    /// or `$HOME` for the current user.
+    ///
+    /// The home directory of another user is looked up below the root, if it is set,
+    /// but not prefixed with it.
+    pub fn home(&self) -> Option<PathBuf> {
+        match &self.user {
+            Some(user) => self.users()
+                .and_then(|users| users.user_by_name(user))
+                .unwrap_or_else(|err| {
+                    warn!("Can not look up user {}: {}", user, err);
+                    None
+                })
+                .map(|user| user.home),
+            None => std::env::var_os("HOME").map(PathBuf::from),
+        }
+    }
+
+    /// Returns if the user qualifies as an administrator under the policy `include_administrative`.
+    ///
+    /// Users which can not be looked up are considered an administrator.
+    pub fn admin(&self, include_administrative: &IncludeAdministrative) -> bool {
+        if let Some(admin) = self.admin {
+            return admin;
+        }
+        let admin = match (&self.user, &self.root) {
+            (user, Some(root)) => include_administrative.check_in_file_system(user.as_deref(), root, self.file_system()),
+            (user, None) => include_administrative.check_with(user.as_deref(), self.user_lookup(), self.file_system()),
+        };
+        admin.unwrap_or_else(|err| {
+            warn!("Can not check administrative rights, assuming an administrator: {}", err);
+            true
+        })
+    }
+
+    /// Returns the `PathFlags` of the system
+    pub fn path_flags(&self, include_administrative: &IncludeAdministrative) -> PathFlags {
+        PathFlags::new()
+            .with_admin(self.admin(include_administrative))
+            .with_os(self.os())
+    }
+
+    /// Returns if `path` is an existing directory, below the root if it is set.
+    pub fn exists(&self, path: &Path) -> bool {
+        if !self.fs_check {
+            return true;
+        }
+        self.file_system.is_dir(&self.in_root(path))
+    }
+
+    /// Returns `path` below the root without symbolic links, which identifies the directory
+    /// it refers to. Paths which can not be resolved are returned below the root as they are,
+    /// all paths are returned as they are without file system checks.
+    pub fn canonicalize(&self, path: &Path) -> PathBuf {
+        if !self.fs_check {
+            return path.to_path_buf();
+        }
+        let path = self.in_root(path);
+        self.file_system.canonicalize(&path).unwrap_or(path)
+    }
+
+    /// Prefixes an absolute `path` with the root, if it is set
+    pub fn in_root(&self, path: &Path) -> PathBuf {
+        match &self.root {
+            Some(root) => root.join(path.strip_prefix("/").unwrap_or(path)),
+            None => path.to_path_buf(),
+        }
+    }
+}
+
+impl Default for SystemProfile {
+    fn default() -> Self {
+        SystemProfile::this_system()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::config::{IncludeAdministrative, MemoryFileSystem, PasswdFiles, PathOs, SystemProfile};
+
+    #[test]
+    fn test_admin() {
+        let profile = SystemProfile::this_system();
+        assert!(profile.admin(&IncludeAdministrative::Always));
+        assert!(!profile.clone().with_admin(false).admin(&IncludeAdministrative::Always));
+        assert!(profile.clone().with_admin(true).admin(&IncludeAdministrative::Never));
+        assert!(profile.clone().with_user("root").admin(&IncludeAdministrative::RootOnly));
+        assert!(!profile.with_user("alice").admin(&IncludeAdministrative::Users(vec!["bob".to_string()])));
+    }
+
+    #[test]
+    fn test_root() {
+        let profile = SystemProfile::this_system()
+            .with_root("/srv/rootfs")
+            .with_os(PathOs::Linux);
+        assert_eq!(profile.in_root(Path::new("/usr/bin")), Path::new("/srv/rootfs/usr/bin"));
+        assert_eq!(profile.os(), PathOs::Linux);
+        assert!(!profile.exists(Path::new("/usr/bin")));
+        assert!(profile.without_fs_check().exists(Path::new("/usr/bin")));
+    }
+
+    #[test]
+    fn test_injected_system() {
+        let profile = SystemProfile::this_system()
+            .with_file_system(MemoryFileSystem::new().with_file("/etc/os-release", "ID=freebsd\n"))
+            .with_user_lookup(PasswdFiles::parse(
+                "alice:x:1000:1000::/home/alice:/bin/sh\nbob:x:1001:1001::/home/bob:/bin/sh\n",
+                "wheel:x:10:alice\n",
+            ).with_current_uid(1001));
+        let wheel = IncludeAdministrative::Groups(vec!["wheel".to_string()]);
+
+        assert_eq!(profile.os(), PathOs::Unix);
+        assert!(!profile.admin(&wheel));
+        let alice = profile.with_user("alice");
+        assert_eq!(alice.home(), Some("/home/alice".into()));
+        assert!(alice.admin(&wheel));
+    }
+}
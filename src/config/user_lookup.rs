@@ -0,0 +1,178 @@
+use std::fmt::Debug;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use users::os::unix::UserExt;
+
+use crate::config::{FileSystem, RealFileSystem};
+
+/// User from the passwd database
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct UserEntry {
+    pub name: String,
+    pub uid: u32,
+    /// Id of the primary group
+    pub gid: u32,
+    pub home: PathBuf,
+}
+
+/// Group from the group database
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct GroupEntry {
+    pub name: String,
+    pub gid: u32,
+}
+
+/// Source of users and their groups, which are checked by `IncludeAdministrative`
+pub trait UserLookup: Debug + Send + Sync {
+    /// Returns the id of the user running pathfix
+    fn current_uid(&self) -> u32;
+
+    fn user_by_uid(&self, uid: u32) -> io::Result<Option<UserEntry>>;
+
+    fn user_by_name(&self, name: &str) -> io::Result<Option<UserEntry>>;
+
+    /// Returns all groups of `user`, including its primary group
+    fn groups(&self, user: &UserEntry) -> io::Result<Vec<GroupEntry>>;
+}
+
+/// Users and groups of the running system
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct SystemUsers;
+
+impl SystemUsers {
+    fn entry(user: users::User) -> UserEntry {
+        UserEntry {
+            name: user.name().to_string_lossy().into_owned(),
+            uid: user.uid(),
+            gid: user.primary_group_id(),
+            home: user.home_dir().to_path_buf(),
+        }
+    }
+}
+
+impl UserLookup for SystemUsers {
+    fn current_uid(&self) -> u32 {
+        users::get_current_uid()
+    }
+
+    fn user_by_uid(&self, uid: u32) -> io::Result<Option<UserEntry>> {
+        Ok(users::get_user_by_uid(uid).map(SystemUsers::entry))
+    }
+
+    fn user_by_name(&self, name: &str) -> io::Result<Option<UserEntry>> {
+        Ok(users::get_user_by_name(name).map(SystemUsers::entry))
+    }
+
+    fn groups(&self, user: &UserEntry) -> io::Result<Vec<GroupEntry>> {
+        Ok(users::get_user_groups(user.name.as_str(), user.gid)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|group| GroupEntry {
+                name: group.name().to_string_lossy().into_owned(),
+                gid: group.gid(),
+            })
+            .collect())
+    }
+}
+
+/// Users and groups from the contents of `passwd` and `group` files
+///
+/// # Examples
+///
+/// ```
+/// use pathfix::config::{IncludeAdministrative, PasswdFiles};
+///
+/// let lookup = PasswdFiles::parse("alice:x:1000:1000::/home/alice:/bin/sh\n", "wheel:x:10:alice\n")
+///     .with_current_uid(1000);
+/// let wheel = IncludeAdministrative::Groups(vec!["wheel".to_string()]);
+///
+/// assert!(wheel.check_current_user_with(&lookup).unwrap());
+/// ```
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct PasswdFiles {
+    current_uid: u32,
+    users: Vec<UserEntry>,
+    groups: Vec<(GroupEntry, Vec<String>)>,
+}
+
+impl PasswdFiles {
+    /// Parses the contents of a `passwd` and a `group` file.
+    ///
+    /// The current user is the user running pathfix, see `with_current_uid`.
+    /// Malformed lines are skipped.
+    pub fn parse(passwd: &str, group: &str) -> PasswdFiles {
+        let users = passwd.lines()
+            .map(|line| line.split(':').collect::<Vec<_>>())
+            .filter(|fields| fields.len() >= 6)
+            .filter_map(|fields| Some(UserEntry {
+                name: fields[0].to_string(),
+                uid: fields[2].parse().ok()?,
+                gid: fields[3].parse().ok()?,
+                home: PathBuf::from(fields[5]),
+            }))
+            .collect();
+        let groups = group.lines()
+            .map(|line| line.split(':').collect::<Vec<_>>())
+            .filter(|fields| fields.len() >= 4)
+            .filter_map(|fields| Some((
+                GroupEntry {
+                    name: fields[0].to_string(),
+                    gid: fields[2].parse().ok()?,
+                },
+                fields[3].split(',').filter(|member| !member.is_empty()).map(str::to_string).collect(),
+            )))
+            .collect();
+        PasswdFiles {
+            current_uid: users::get_current_uid(),
+            users,
+            groups,
+        }
+    }
+
+    /// Reads `etc/passwd` and `etc/group` below `root`, e.g. of a container image.
+    ///
+    /// A missing `etc/group` is treated as empty.
+    pub fn in_root<P: AsRef<Path>>(root: P) -> io::Result<PasswdFiles> {
+        PasswdFiles::in_root_with(root, &RealFileSystem)
+    }
+
+    /// Like `in_root`, but reads the files from `file_system`.
+    pub fn in_root_with<P: AsRef<Path>>(root: P, file_system: &dyn FileSystem) -> io::Result<PasswdFiles> {
+        let passwd = file_system.read_to_string(&root.as_ref().join("etc/passwd"))?;
+        let group = match file_system.read_to_string(&root.as_ref().join("etc/group")) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            result => result?,
+        };
+        Ok(PasswdFiles::parse(&passwd, &group))
+    }
+
+    /// Pretends that the user `uid` is running pathfix
+    pub fn with_current_uid(self, uid: u32) -> PasswdFiles {
+        PasswdFiles {
+            current_uid: uid,
+            ..self
+        }
+    }
+}
+
+impl UserLookup for PasswdFiles {
+    fn current_uid(&self) -> u32 {
+        self.current_uid
+    }
+
+    fn user_by_uid(&self, uid: u32) -> io::Result<Option<UserEntry>> {
+        Ok(self.users.iter().find(|user| user.uid == uid).cloned())
+    }
+
+    fn user_by_name(&self, name: &str) -> io::Result<Option<UserEntry>> {
+        Ok(self.users.iter().find(|user| user.name == name).cloned())
+    }
+
+    fn groups(&self, user: &UserEntry) -> io::Result<Vec<GroupEntry>> {
+        Ok(self.groups.iter()
+            .filter(|(group, members)| group.gid == user.gid || members.contains(&user.name))
+            .map(|(group, _)| group.clone())
+            .collect())
+    }
+}
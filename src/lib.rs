@@ -9,5 +9,13 @@ extern crate users;
 #[macro_use]
 extern crate log;
 extern crate thiserror;
+extern crate regex;
 
 pub mod config;
+
+mod resolver;
+pub use resolver::{
+    DropReason, DroppedPath, Resolution, ResolveError, ResolveResult, ResolvedPath, Resolver,
+    SYSTEM_CONFIG_FILE,
+};
+pub use config::DedupMode;
@@ -0,0 +1,538 @@
+//! Generation of the `$PATH` from configuration sources
+
+use std::collections::HashMap;
+use std::env::VarError;
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path as FsPath, PathBuf};
+use std::sync::Arc;
+
+use log::Level::Debug;
+use thiserror::Error;
+
+use crate::config::{
+    resolve_env_with, CommandCache, Config, ConfigFileDescription, ConfigFileFormat, ConfigSource,
+    DedupMode, EnvError, EnvValue, IncludeAdministrative, PasswdFiles, Path, Paths, SystemProfile,
+    PATH_HELPER_DIR, PATH_HELPER_FILE,
+};
+
+/// Location of the system configuration, below the root of the `SystemProfile`
+pub const SYSTEM_CONFIG_FILE: &str = "/etc/pathfix.toml";
+
+#[derive(Error, Debug)]
+pub enum ResolveError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Var(#[from] VarError),
+    #[error(transparent)]
+    Env(#[from] EnvError),
+    /// Descriptions of the missing paths in strict mode
+    #[error("{} required path(s) are missing", .0.len())]
+    MissingRequired(Vec<String>),
+}
+
+pub type ResolveResult<T> = Result<T, ResolveError>;
+
+/// Generates the `$PATH` from configuration sources
+///
+/// Sources are read in this order, where later sources take precedence:
+/// `/etc/paths` and `/etc/paths.d` if enabled, the built-in configuration, `/etc/pathfix.toml`,
+/// the home configuration, `$PATH` and explicitly given configuration files.
+/// The built-in and the system configuration are skipped once a configuration sets `base`.
+///
+/// # Examples
+///
+/// ```
+/// use pathfix::Resolver;
+/// use pathfix::config::{MemoryFileSystem, SystemProfile};
+///
+/// let file_system = MemoryFileSystem::new()
+///     .with_file("/etc/pathfix.toml", "base = true\npaths = [\"/usr/bin\", \"/opt/missing/bin\"]\n")
+///     .with_dir("/usr/bin");
+/// let profile = SystemProfile::this_system()
+///     .with_root("/")
+///     .with_file_system(file_system);
+///
+/// let resolution = Resolver::new(profile).with_included().resolve().unwrap();
+/// assert_eq!(resolution.path(), vec![std::path::PathBuf::from("/usr/bin")]);
+/// assert_eq!(resolution.dropped.len(), 1);
+/// ```
+#[derive(Debug)]
+pub struct Resolver {
+    profile: SystemProfile,
+    path_var: bool,
+    included: bool,
+    path_helper: bool,
+    home_config: Option<PathBuf>,
+    config_files: Vec<PathBuf>,
+    env: HashMap<String, EnvValue>,
+    dedup: DedupMode,
+    strict: bool,
+    process_env: bool,
+    command_cache: CommandCache,
+}
+
+impl Resolver {
+    /// Creates a resolver without any sources for the system `profile`
+    pub fn new(profile: SystemProfile) -> Resolver {
+        Resolver {
+            profile,
+            path_var: false,
+            included: false,
+            path_helper: false,
+            home_config: None,
+            config_files: Vec::new(),
+            env: HashMap::new(),
+            dedup: DedupMode::Keep,
+            strict: false,
+            process_env: true,
+            command_cache: CommandCache::disabled(),
+        }
+    }
+
+    /// Adds the entries of `$PATH`
+    pub fn with_path_var(self) -> Resolver {
+        Resolver {
+            path_var: true,
+            ..self
+        }
+    }
+
+    /// Adds the home configuration, if set, `/etc/pathfix.toml` and the built-in configuration
+    pub fn with_included(self) -> Resolver {
+        Resolver {
+            included: true,
+            ..self
+        }
+    }
+
+    /// Sets the home configuration, which is read if it exists
+    pub fn with_home_config<P: Into<PathBuf>>(self, path: P) -> Resolver {
+        Resolver {
+            home_config: Some(path.into()),
+            ..self
+        }
+    }
+
+    /// Adds a configuration file, which has to exist
+    ///
+    /// The format is chosen by the extension, e.g. `.txt`, files without one are read as TOML.
+    pub fn with_config_file<P: Into<PathBuf>>(mut self, path: P) -> Resolver {
+        self.config_files.push(path.into());
+        self
+    }
+
+    /// Sets an environment variable, which takes precedence over the process environment
+    pub fn with_env_var<S: Into<String>>(mut self, name: S, value: EnvValue) -> Resolver {
+        self.env.insert(name.into(), value);
+        self
+    }
+
+    pub fn with_dedup(self, dedup: DedupMode) -> Resolver {
+        Resolver {
+            dedup,
+            ..self
+        }
+    }
+
+    /// Keeps all paths instead of checking if they exist, see `SystemProfile::without_fs_check`
+    pub fn without_existence_check(self) -> Resolver {
+        Resolver {
+            profile: self.profile.without_fs_check(),
+            ..self
+        }
+    }
+
+    /// Reads `/etc/paths` and `/etc/paths.d` like macOS `path_helper`, with a lower precedence
+    /// than all other configuration files
+    pub fn with_path_helper(self) -> Resolver {
+        Resolver {
+            path_helper: true,
+            ..self
+        }
+    }
+
+    /// Ignores the variables of the process environment, so configured values are not replaced
+    /// by them, e.g. to dump the configuration. Variables of `with_env_var` are still used.
+    pub fn without_process_env(self) -> Resolver {
+        Resolver {
+            process_env: false,
+            ..self
+        }
+    }
+
+    /// Fails if a required path is missing, instead of returning a warning
+    pub fn with_strict(self) -> Resolver {
+        Resolver {
+            strict: true,
+            ..self
+        }
+    }
+
+    /// Caches the outputs of environment variable commands
+    pub fn with_command_cache(self, command_cache: CommandCache) -> Resolver {
+        Resolver {
+            command_cache,
+            ..self
+        }
+    }
+
+    pub fn profile(&self) -> &SystemProfile {
+        &self.profile
+    }
+
+    /// Reads all sources and generates the `$PATH`.
+    ///
+    /// The command cache is saved afterwards.
+    pub fn resolve(&mut self) -> ResolveResult<Resolution> {
+        let mut config = self.load()?;
+
+        // `~user` refers to the users of the evaluated system
+        let users = self.profile.users().unwrap_or_else(|err| {
+            warn!("Can not read users below {}: {}", self.profile.root().unwrap_or(FsPath::new("/")).display(), err);
+            Arc::new(PasswdFiles::default())
+        });
+        let env = resolve_env_with(&config.env, &mut self.command_cache, users.as_ref())?;
+        if let Err(err) = self.command_cache.save() {
+            warn!("Could not save command cache: {}", err);
+        }
+
+        let all_paths = std::mem::take(&mut config.paths);
+        let (paths, excluded) = config.exclude.partition_with(all_paths, &env, users.as_ref())?;
+        let mut dropped: Vec<DroppedPath> = excluded.into_iter()
+            .map(|path| DroppedPath::new(path, None, DropReason::Excluded))
+            .collect();
+
+        let include_administrative = config.include_administrative.as_ref()
+            .unwrap_or(&IncludeAdministrative::RootOnly);
+        let path_flags = self.profile.path_flags(include_administrative);
+        let exists = |path: &FsPath| self.profile.exists(path);
+
+        let missing = paths.missing_required_with(path_flags, &env, users.as_ref(), exists);
+        let warnings: Vec<String> = missing.iter().map(ToString::to_string).collect();
+        if self.strict && !warnings.is_empty() {
+            return Err(ResolveError::MissingRequired(warnings));
+        }
+
+        // same order as `Paths::resolve`
+        let mut applicable: Vec<&Path> = Vec::new();
+        for path in &paths {
+            if !path.flags().check(path_flags) {
+                dropped.push(DroppedPath::new(path.clone(), None, DropReason::OtherSystem));
+            } else if !path.check_condition(&env) {
+                dropped.push(DroppedPath::new(path.clone(), None, DropReason::Condition));
+            } else {
+                applicable.push(path);
+            }
+        }
+        applicable.sort_by_key(|path| std::cmp::Reverse(path.priority()));
+
+        let mut applicable_resolved = Vec::new();
+        for path in applicable {
+            match path.resolve_with(&env, users.as_ref()) {
+                Some(resolved_path) => applicable_resolved.push((path, resolved_path)),
+                None => dropped.push(DroppedPath::new(path.clone(), None, DropReason::Unresolved)),
+            }
+        }
+
+        // entries which refer to the same directory through symbolic links are duplicates as well
+        let keys: Vec<PathBuf> = applicable_resolved.iter()
+            .map(|(_, resolved_path)| self.profile.canonicalize(resolved_path))
+            .collect();
+        let duplicates = self.dedup.duplicates(&keys);
+        let mut resolved = Vec::new();
+        for ((path, resolved_path), duplicate) in applicable_resolved.into_iter().zip(duplicates) {
+            let reason = if duplicate {
+                Some(DropReason::Duplicate)
+            } else if !exists(&resolved_path) {
+                Some(DropReason::Missing)
+            } else {
+                None
+            };
+            match reason {
+                Some(reason) => dropped.push(DroppedPath::new(path.clone(), Some(resolved_path), reason)),
+                None => resolved.push(ResolvedPath { resolved: resolved_path, path: path.clone() }),
+            }
+        }
+
+        debug!("IncludeAdministrative: {:?}", config.include_administrative.clone().unwrap_or_default());
+        debug!("Use admin paths: {:?}", path_flags.admin());
+
+        config.paths = paths;
+        Ok(Resolution {
+            paths: resolved,
+            dropped,
+            warnings,
+            config,
+            env,
+        })
+    }
+
+    /// Reads and merges all configuration sources, without generating the `$PATH`.
+    ///
+    /// The sources of the settings are kept, see `Config::dump`.
+    pub fn load(&self) -> ResolveResult<Config> {
+        let mut config = if self.process_env { Config::new().with_env() } else { Config::new() };
+        for (name, value) in &self.env {
+            config.env.insert(name.clone(), value.clone());
+        }
+
+        if self.path_var {
+            config.paths = Paths::from_env()?;
+            info!("Loaded contents of $PATH variable");
+        }
+
+        if self.included {
+            if let Some(home_config_file) = &self.home_config {
+                if let Some(home_config) = self.load_optional(home_config_file, ConfigSource::Home(ConfigFileFormat::Toml))? {
+                    config = home_config.merge(config);
+                    info!("Loaded config from {}", home_config_file.display());
+                } else {
+                    info!("{} is missing", home_config_file.display())
+                }
+            }
+
+            if !config.base {
+                let system_config_file = self.profile.in_root(FsPath::new(SYSTEM_CONFIG_FILE));
+                if let Some(system_config) = self.load_optional(&system_config_file, ConfigSource::System(ConfigFileFormat::Toml))? {
+                    config = system_config.merge(config);
+                    info!("Loaded config from {}", system_config_file.display());
+                } else {
+                    info!("{} is missing", system_config_file.display())
+                }
+            }
+
+            if !config.base {
+                config = Config::included().merge(config);
+                info!("Loaded included config");
+            }
+        }
+
+        if self.path_helper {
+            config = ConfigSource::PathHelper.open_for(&self.profile)?.merge(config);
+            info!("Loaded {} and {}", PATH_HELPER_FILE, PATH_HELPER_DIR);
+        }
+
+        for config_file in &self.config_files {
+            let description = ConfigFileDescription::from_path(config_file)?;
+            let mut file_config = description.open_with(self.profile.file_system())?;
+            file_config.set_source(ConfigSource::Config(description));
+            config = file_config.merge(config);
+            info!("Loaded specified config file {}", config_file.display());
+        }
+
+        debug!("Merged config:");
+        if log_enabled!(Debug) {
+            for path in config.paths.iter() {
+                if let Some(path_source) = path.source() {
+                    debug!("{:30} | {:15} | {:30}", path.path().to_string_lossy(), path.flags().to_string(), path_source);
+                } else {
+                    debug!("{:30} | {:15} |", path.path().to_string_lossy(), path.flags().to_string());
+                }
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Reads a configuration file, which might not exist
+    fn load_optional(&self, path: &FsPath, source: ConfigSource) -> io::Result<Option<Config>> {
+        match Config::from_file_with(path, self.profile.file_system()) {
+            Ok(mut config) => {
+                config.set_source(source);
+                Ok(Some(config))
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// Result of `Resolver::resolve`
+#[derive(Debug, Clone)]
+pub struct Resolution {
+    /// Existing paths, in order
+    pub paths: Vec<ResolvedPath>,
+    /// Configured paths which are not part of the `$PATH`
+    pub dropped: Vec<DroppedPath>,
+    /// Descriptions of missing required paths
+    pub warnings: Vec<String>,
+    /// Merged configuration
+    pub config: Config,
+    /// Resolved environment variables
+    pub env: HashMap<String, OsString>,
+}
+
+impl Resolution {
+    /// Returns the `$PATH` entries
+    pub fn path(&self) -> Vec<PathBuf> {
+        self.paths.iter().map(|path| path.resolved.clone()).collect()
+    }
+
+    /// Returns all paths whose existence was checked
+    pub fn candidates(&self) -> Vec<PathBuf> {
+        let missing = self.dropped.iter()
+            .filter(|dropped| dropped.reason == DropReason::Missing)
+            .filter_map(|dropped| dropped.resolved.clone());
+        self.path().into_iter().chain(missing).collect()
+    }
+}
+
+/// Entry of the `$PATH` and the configured path it comes from
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ResolvedPath {
+    pub resolved: PathBuf,
+    pub path: Path,
+}
+
+impl ResolvedPath {
+    pub fn source(&self) -> Option<&Arc<ConfigSource>> {
+        self.path.source()
+    }
+}
+
+/// Configured path which is not part of the `$PATH`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DroppedPath {
+    pub path: Path,
+    /// Resolved path, if it was resolved before it was dropped
+    pub resolved: Option<PathBuf>,
+    pub reason: DropReason,
+}
+
+impl DroppedPath {
+    fn new(path: Path, resolved: Option<PathBuf>, reason: DropReason) -> DroppedPath {
+        DroppedPath { path, resolved, reason }
+    }
+
+    pub fn source(&self) -> Option<&Arc<ConfigSource>> {
+        self.path.source()
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DropReason {
+    /// Matches an exclude rule
+    Excluded,
+    /// Flags do not match the system
+    OtherSystem,
+    /// Condition variable is not set
+    Condition,
+    /// Variables can not be expanded
+    Unresolved,
+    /// Resolves to a previous path
+    Duplicate,
+    /// Directory does not exist
+    Missing,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::config::{MemoryFileSystem, SystemProfile};
+    use crate::{DedupMode, DropReason, ResolveError, Resolver};
+
+    fn profile() -> SystemProfile {
+        let file_system = MemoryFileSystem::new()
+            .with_file("/etc/pathfix.toml", r#"
+                base = true
+
+                [[paths]]
+                path = "/usr/bin"
+
+                [[paths]]
+                path = "/usr/local/bin"
+                priority = 1
+
+                [[paths]]
+                path = "/usr/bin"
+
+                [[paths]]
+                path = "/opt/missing/bin"
+
+                [[paths]]
+                path = "/opt/corp/bin"
+                required = true
+
+                [[paths]]
+                path = "$PATHFIX_TEST_UNSET/bin"
+
+                [[paths]]
+                path = "/sbin"
+                flags = "windows"
+            "#)
+            .with_dir("/usr/bin")
+            .with_dir("/usr/local/bin");
+        SystemProfile::this_system()
+            .with_root("/")
+            .with_file_system(file_system)
+    }
+
+    #[test]
+    fn test_resolve() {
+        let resolution = Resolver::new(profile())
+            .with_included()
+            .with_dedup(DedupMode::KeepFirst)
+            .resolve()
+            .unwrap();
+        assert_eq!(resolution.path(), vec![PathBuf::from("/usr/local/bin"), PathBuf::from("/usr/bin")]);
+        let reasons: Vec<_> = resolution.dropped.iter()
+            .map(|dropped| (dropped.path.path().to_string_lossy().into_owned(), dropped.reason))
+            .collect();
+        assert_eq!(reasons, vec![
+            ("/sbin".to_string(), DropReason::OtherSystem),
+            ("$PATHFIX_TEST_UNSET/bin".to_string(), DropReason::Unresolved),
+            ("/usr/bin".to_string(), DropReason::Duplicate),
+            ("/opt/missing/bin".to_string(), DropReason::Missing),
+            ("/opt/corp/bin".to_string(), DropReason::Missing),
+        ]);
+        assert_eq!(resolution.warnings.len(), 1);
+        assert_eq!(resolution.candidates().len(), 4);
+        assert!(resolution.paths.iter().all(|path| path.source().is_some()));
+    }
+
+    #[test]
+    fn test_dedup_symlink() {
+        let profile = profile().with_file_system(MemoryFileSystem::new()
+            .with_file("/etc/pathfix.toml", "base = true\npaths = [\"/usr/bin\", \"/bin\", \"/usr/sbin\"]\n")
+            .with_dir("/usr/bin")
+            .with_dir("/usr/sbin")
+            .with_symlink("/bin", "usr/bin"));
+        let resolution = Resolver::new(profile)
+            .with_included()
+            .with_dedup(DedupMode::KeepFirst)
+            .resolve()
+            .unwrap();
+        assert_eq!(resolution.path(), vec![PathBuf::from("/usr/bin"), PathBuf::from("/usr/sbin")]);
+        assert_eq!(resolution.dropped.len(), 1);
+        assert_eq!(resolution.dropped[0].path.path(), "/bin");
+        assert_eq!(resolution.dropped[0].reason, DropReason::Duplicate);
+    }
+
+    #[test]
+    fn test_user_home() {
+        let profile = profile().with_file_system(MemoryFileSystem::new()
+            .with_file("/etc/pathfix.toml", "paths = [\"~pathfix-test/bin\", \"~pathfix-unknown/bin\"]\n")
+            .with_file("/etc/passwd", "pathfix-test:x:1000:1000::/srv/pathfix-test:/bin/sh\n")
+            .with_dir("/srv/pathfix-test/bin"));
+        let resolution = Resolver::new(profile).with_included().resolve().unwrap();
+        assert_eq!(resolution.path(), vec![PathBuf::from("/srv/pathfix-test/bin")]);
+        let unknown = resolution.dropped.iter().find(|dropped| dropped.path.path() == "~pathfix-unknown/bin").unwrap();
+        assert_eq!(unknown.reason, DropReason::Unresolved);
+    }
+
+    #[test]
+    fn test_strict() {
+        let result = Resolver::new(profile()).with_included().with_strict().resolve();
+        assert!(matches!(result, Err(ResolveError::MissingRequired(missing)) if missing.len() == 1));
+
+        let resolution = Resolver::new(profile())
+            .with_included()
+            .without_existence_check()
+            .resolve()
+            .unwrap();
+        assert_eq!(resolution.path().len(), 5);
+    }
+}
@@ -1,22 +1,55 @@
 extern crate assert_cmd;
+extern crate predicates;
+
+use std::path::{Path, PathBuf};
 
 use assert_cmd::Command;
+use predicates::prelude::*;
+use predicates::str::contains;
+
+/// Returns the cache directory of a single test, so neither the user's cache nor other tests are used
+fn cache_home(test: &str) -> PathBuf {
+    Path::new(env!("CARGO_TARGET_TMPDIR")).join("cache").join(test)
+}
+
+fn pathfix(test: &str) -> Command {
+    let mut cmd = Command::cargo_bin("pathfix").unwrap();
+    cmd.env("XDG_CACHE_HOME", cache_home(test));
+    cmd
+}
 
 #[test]
 fn test_env_dedup() {
-    let mut cmd = Command::cargo_bin("pathfix").unwrap();
+    let mut cmd = pathfix("env_dedup");
     let assert = cmd
         .arg("-ed")
-        .env("PATH", "/usr/bin:/usr/bin:/bin")
+        .env("PATH", "./tests/a:./tests/a:./tests/b")
         .assert();
     assert
         .success()
-        .stdout("/usr/bin:/bin\n");
+        .stdout("./tests/a:./tests/b\n");
+}
+
+/// Entries which link to the same directory are duplicates as well.
+#[test]
+fn test_env_dedup_symlink() {
+    let link = Path::new(env!("CARGO_TARGET_TMPDIR")).join("dedup-link");
+    let _ = std::fs::remove_file(&link);
+    std::os::unix::fs::symlink(std::fs::canonicalize("./tests/a").unwrap(), &link).unwrap();
+
+    let mut cmd = pathfix("env_dedup_symlink");
+    let assert = cmd
+        .arg("-ed")
+        .env("PATH", format!("./tests/a:{}:./tests/b", link.display()))
+        .assert();
+    assert
+        .success()
+        .stdout("./tests/a:./tests/b\n");
 }
 
 #[test]
 fn test_lines() {
-    let mut cmd = Command::cargo_bin("pathfix").unwrap();
+    let mut cmd = pathfix("lines");
     let assert = cmd
         .arg("-el")
         .env("PATH", "/usr/bin:/usr/bin:/bin")
@@ -30,7 +63,7 @@ fn test_lines() {
 /// Not giving any cli arguments is an error.
 #[test]
 fn test_no_args() {
-    let mut cmd = Command::cargo_bin("pathfix").unwrap();
+    let mut cmd = pathfix("no_args");
     let assert = cmd
         .env("PATH", "/usr/bin:/usr/bin:/bin")
         .assert();
@@ -40,7 +73,7 @@ fn test_no_args() {
 
 #[test]
 fn test_config() {
-    let mut cmd = Command::cargo_bin("pathfix").unwrap();
+    let mut cmd = pathfix("config");
     let assert = cmd
         .env("B_PATH", "./tests/b")
         .arg("--config=./tests/small.toml")
@@ -49,3 +82,484 @@ fn test_config() {
         .success()
         .stdout("./tests/a:./tests/b/c\n");
 }
+
+/// Config files with an unknown extension are rejected with the supported formats.
+#[test]
+fn test_config_unknown_extension() {
+    let config = Path::new(env!("CARGO_TARGET_TMPDIR")).join("small.conf");
+    std::fs::copy("./tests/small.toml", &config).unwrap();
+
+    let mut cmd = pathfix("config_unknown_extension");
+    let assert = cmd
+        .env("B_PATH", "./tests/b")
+        .arg("-c")
+        .arg(&config)
+        .assert();
+    assert
+        .failure()
+        .stderr(contains("File extension conf not known, use 'toml', 'txt', 'paths', 'json' or 'yaml'"));
+}
+
+#[test]
+fn test_exclude() {
+    let mut cmd = pathfix("exclude");
+    let assert = cmd
+        .arg("-e")
+        .arg("--config=./tests/exclude.toml")
+        .env("PATH", "/usr/bin:.:/bin")
+        .assert();
+    assert
+        .success()
+        .stdout("/usr/bin:/bin\n");
+}
+
+#[test]
+fn test_required_warning() {
+    let mut cmd = pathfix("required_warning");
+    let assert = cmd
+        .arg("--config=./tests/required.toml")
+        .assert();
+    assert
+        .success()
+        .stdout("./tests/a\n")
+        .stderr(contains("required path ./tests/missing does not exist"));
+}
+
+#[test]
+fn test_required_strict() {
+    let mut cmd = pathfix("required_strict");
+    let assert = cmd
+        .arg("--strict")
+        .arg("--config=./tests/required.toml")
+        .assert();
+    assert
+        .code(2)
+        .stderr(contains("config: ./tests/required.toml"));
+}
+
+#[test]
+fn test_env_command() {
+    let mut cmd = pathfix("env_command");
+    let assert = cmd
+        .arg("--config=./tests/command.toml")
+        .assert();
+    assert
+        .success()
+        .stdout("./tests/a\n");
+}
+
+/// Paths which are not valid UTF-8 are passed through unchanged.
+#[test]
+fn test_non_utf8_path() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(OsStr::from_bytes(b"j\xf6rg"));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut path = b"/usr/bin:".to_vec();
+    path.extend_from_slice(dir.as_os_str().as_bytes());
+    let mut stdout = path.clone();
+    stdout.push(b'\n');
+
+    let mut cmd = pathfix("non_utf8_path");
+    let assert = cmd
+        .arg("-e")
+        .env("PATH", OsStr::from_bytes(&path))
+        .env("NAME", OsStr::from_bytes(b"j\xf6rg"))
+        .assert();
+    assert
+        .success()
+        .stdout(stdout);
+}
+
+/// Cached results are invalidated if a candidate directory disappears.
+#[test]
+fn test_result_cache() {
+    let cache = cache_home("result_cache");
+    let _ = std::fs::remove_dir_all(&cache);
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("result-cache/sdk");
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = format!("/usr/bin:{}", dir.display());
+
+    let run = || {
+        pathfix("result_cache")
+            .arg("-e")
+            .env("PATH", &path)
+            .assert()
+            .success()
+    };
+    run().stdout(format!("{}\n", path));
+    assert!(cache.join("pathfix/results.toml").exists());
+    run().stdout(format!("{}\n", path));
+
+    std::fs::remove_dir(&dir).unwrap();
+    run().stdout("/usr/bin\n");
+
+    pathfix("result_cache")
+        .args(["cache", "clear"])
+        .assert()
+        .success()
+        .stdout("");
+    assert!(!cache.join("pathfix").exists());
+}
+
+/// Results with --path-helper are keyed on /etc/paths and /etc/paths.d, which only change with the system.
+#[test]
+fn test_result_cache_path_helper() {
+    let cache = cache_home("result_cache_path_helper");
+    let _ = std::fs::remove_dir_all(&cache);
+
+    let run = || {
+        pathfix("result_cache_path_helper")
+            .args(["-e", "--path-helper"])
+            .env("PATH", "/usr/bin")
+            .assert()
+            .success()
+    };
+    let output = String::from_utf8(run().get_output().stdout.clone()).unwrap();
+    let results = cache.join("pathfix/results.toml");
+    let stored = format!("output = {:?}", output);
+    let cached = std::fs::read_to_string(&results).unwrap();
+    assert!(cached.contains(&stored), "{}", cached);
+
+    // the stored output is returned, as long as the key of the run did not change
+    std::fs::write(&results, cached.replace(&stored, "output = \"cached\\n\"")).unwrap();
+    run().stdout("cached\n");
+}
+
+/// Results of configurations with commands are not cached, as their programs can change.
+#[test]
+fn test_result_cache_command() {
+    use std::os::unix::fs::PermissionsExt;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let cache = cache_home("result_cache_command");
+    let _ = std::fs::remove_dir_all(&cache);
+    let tmp = Path::new(env!("CARGO_TARGET_TMPDIR")).join("result-cache-command");
+    for dir in &["one/bin", "two/bin"] {
+        std::fs::create_dir_all(tmp.join(dir)).unwrap();
+    }
+    let script = tmp.join("sdk-root");
+    let config = tmp.join("pathfix.toml");
+    std::fs::write(&config, format!(
+        "paths = [\"$SDK_ROOT/bin\"]\n\n[env]\nSDK_ROOT = {{ command = [\"{}\"] }}\n", script.display(),
+    )).unwrap();
+
+    let run = |sdk: &str, mtime: u64| {
+        std::fs::write(&script, format!("#!/bin/sh\necho {}\n", tmp.join(sdk).display())).unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        // the command cache only notices the change with a different modification time
+        std::fs::File::options().write(true).open(&script).unwrap()
+            .set_modified(UNIX_EPOCH + Duration::from_secs(mtime)).unwrap();
+        pathfix("result_cache_command")
+            .arg("-c")
+            .arg(&config)
+            .assert()
+            .success()
+            .stdout(format!("{}\n", tmp.join(sdk).join("bin").display()));
+    };
+    run("one", 1_600_000_000);
+    run("two", 1_700_000_000);
+    assert!(!cache.join("pathfix/results.toml").exists());
+}
+
+/// Results of policies which read sudoers files are not cached, as the files can include others.
+#[test]
+fn test_result_cache_sudoers() {
+    let cache = cache_home("result_cache_sudoers");
+    let _ = std::fs::remove_dir_all(&cache);
+    let tmp = Path::new(env!("CARGO_TARGET_TMPDIR")).join("result-cache-sudoers");
+    for dir in &["bin", "admin/bin"] {
+        std::fs::create_dir_all(tmp.join(dir)).unwrap();
+    }
+    let sudoers = tmp.join("sudoers");
+    let config = tmp.join("pathfix.toml");
+    std::fs::write(&config, format!(
+        "include_administrative = {{ sudo-nopasswd = \"{}\" }}\n\n[paths]\n\"{}\" = \"\"\n\"{}\" = \"admin\"\n",
+        sudoers.display(), tmp.join("bin").display(), tmp.join("admin/bin").display(),
+    )).unwrap();
+
+    let run = |sudoers_content: &str| {
+        std::fs::write(&sudoers, sudoers_content).unwrap();
+        pathfix("result_cache_sudoers")
+            .arg("-c")
+            .arg(&config)
+            .assert()
+            .success()
+    };
+    run("ALL ALL=(ALL) NOPASSWD: ALL\n")
+        .stdout(format!("{}:{}\n", tmp.join("bin").display(), tmp.join("admin/bin").display()));
+    run("").stdout(format!("{}\n", tmp.join("bin").display()));
+    assert!(!cache.join("pathfix/results.toml").exists());
+}
+
+#[test]
+fn test_exec() {
+    let mut cmd = pathfix("exec");
+    let assert = cmd
+        .args(["--config=./tests/command.toml", "exec", "--", "/bin/sh", "-c", "echo $PATH $TESTS"])
+        .assert();
+    assert
+        .success()
+        .stdout("./tests/a ./tests\n");
+}
+
+/// The command is not executed if required paths are missing in strict mode.
+#[test]
+fn test_exec_strict() {
+    pathfix("exec_strict")
+        .args(["--strict", "--config=./tests/required.toml", "exec", "echo", "executed"])
+        .assert()
+        .code(2)
+        .stdout("")
+        .stderr(contains("required path(s) are missing"));
+}
+
+/// The command is run with the fallback path string if an error occurs.
+#[test]
+fn test_exec_failsafe() {
+    let mut cmd = pathfix("exec_failsafe");
+    let assert = cmd
+        .args(["--config=./tests/nonexistent.toml", "exec", "/bin/sh", "-c", "echo $PATH; exit 3"])
+        .assert();
+    assert
+        .code(3)
+        .stdout("/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin\n")
+        .stderr(contains("Failure"));
+}
+
+#[test]
+fn test_export() {
+    let mut cmd = pathfix("export");
+    let assert = cmd
+        .args(["--export", "--config=./tests/export.toml"])
+        .assert();
+    assert
+        .success()
+        .stdout("export PATH='./tests/a'\nexport QUOTED='it'\\''s ./tests'\nexport TESTS='./tests'\n");
+}
+
+#[test]
+fn test_systemd() {
+    let mut cmd = pathfix("systemd");
+    let assert = cmd
+        .args(["--systemd", "--config=./tests/export.toml"])
+        .assert();
+    assert
+        .success()
+        .stdout("PATH=./tests/a\nQUOTED=\"it's ./tests\"\nTESTS=./tests\n");
+}
+
+/// Admin paths are always included in system wide outputs.
+#[test]
+fn test_system_wide() {
+    pathfix("system_wide")
+        .arg("--config=./tests/admin.toml")
+        .assert()
+        .success()
+        .stdout("./tests/b\n");
+    pathfix("system_wide")
+        .args(["--sudoers", "--config=./tests/admin.toml"])
+        .assert()
+        .success()
+        .stdout("Defaults secure_path=\"./tests/a:./tests/b\"\n");
+    pathfix("system_wide")
+        .args(["--etc-environment", "--config=./tests/admin.toml"])
+        .assert()
+        .success()
+        .stdout("PATH=\"./tests/a:./tests/b\"\n");
+}
+
+/// System wide outputs neither read $PATH nor the home directory of the invoking user.
+#[test]
+fn test_system_wide_invoker() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("system-wide-invoker");
+    let _ = std::fs::remove_dir_all(&dir);
+    for subdir in &["home/.cargo/bin", "home/bin", "home-config/bin", "path/bin"] {
+        std::fs::create_dir_all(dir.join(subdir)).unwrap();
+    }
+    std::fs::write(
+        dir.join("home/.pathfix.toml"),
+        format!("paths = [\"{}\"]\n", dir.join("home-config/bin").display()),
+    ).unwrap();
+
+    for format in &["--sudoers", "--etc-environment"] {
+        pathfix("system_wide_invoker")
+            .args(["-i", format])
+            .env("HOME", dir.join("home"))
+            .env("PATH", format!("{}:/usr/bin:/bin", dir.join("path/bin").display()))
+            .assert()
+            .success()
+            .stdout(contains("system-wide-invoker").not());
+    }
+}
+
+/// With --root the system config and the existence checks are taken from the root.
+#[test]
+fn test_root() {
+    let root = Path::new(env!("CARGO_TARGET_TMPDIR")).join("rootfs");
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(root.join("etc")).unwrap();
+    std::fs::create_dir_all(root.join("opt/pathfix-test/bin")).unwrap();
+    std::fs::write(
+        root.join("etc/pathfix.toml"),
+        "base = true\npaths = [\"/opt/pathfix-test/bin\", \"/opt/pathfix-missing/bin\"]\n",
+    ).unwrap();
+
+    let mut cmd = pathfix("root");
+    let assert = cmd
+        .arg("-i")
+        .arg("--root")
+        .arg(&root)
+        .assert();
+    assert
+        .success()
+        .stdout("/opt/pathfix-test/bin\n");
+}
+
+#[test]
+fn test_path_helper() {
+    let root = Path::new(env!("CARGO_TARGET_TMPDIR")).join("rootfs-path-helper");
+    let _ = std::fs::remove_dir_all(&root);
+    for dir in &["etc/paths.d", "opt/pathfix/bin", "opt/helper/bin", "opt/tex/bin"] {
+        std::fs::create_dir_all(root.join(dir)).unwrap();
+    }
+    std::fs::write(root.join("etc/pathfix.toml"), "base = true\npaths = [\"/opt/pathfix/bin\"]\n").unwrap();
+    std::fs::write(root.join("etc/paths"), "/opt/helper/bin\n/opt/missing/bin\n").unwrap();
+    std::fs::write(root.join("etc/paths.d/TeX"), "/opt/tex/bin\n").unwrap();
+
+    let mut cmd = pathfix("path_helper");
+    let assert = cmd
+        .args(["-i", "--lines", "--path-helper", "--root"])
+        .arg(&root)
+        .assert();
+    assert
+        .success()
+        .stdout("/opt/pathfix/bin\n/opt/helper/bin\n/opt/tex/bin\n");
+}
+
+#[test]
+fn test_user() {
+    let root = Path::new(env!("CARGO_TARGET_TMPDIR")).join("rootfs-user");
+    let _ = std::fs::remove_dir_all(&root);
+    for dir in &["etc", "home/bob/bin", "opt/admin/bin"] {
+        std::fs::create_dir_all(root.join(dir)).unwrap();
+    }
+    std::fs::write(root.join("etc/passwd"), "bob:x:1000:1000::/home/bob:/bin/sh\n").unwrap();
+    std::fs::write(root.join("etc/group"), "bob:x:1000:\nwheel:x:10:bob\n").unwrap();
+    std::fs::write(
+        root.join("home/bob/.pathfix.toml"),
+        "base = true\ninclude_administrative = { groups = [\"wheel\"] }\n\n\
+        [paths]\n\"~/bin\" = \"\"\n\"/opt/admin/bin\" = \"admin\"\n",
+    ).unwrap();
+
+    let mut cmd = pathfix("user");
+    let assert = cmd
+        .args(["-i", "--user", "bob", "--root"])
+        .arg(&root)
+        .assert();
+    assert
+        .success()
+        .stdout("/home/bob/bin:/opt/admin/bin\n");
+
+    let mut cmd = pathfix("user");
+    let assert = cmd
+        .args(["-i", "--user", "alice", "--root"])
+        .arg(&root)
+        .assert();
+    assert
+        .failure()
+        .stderr(contains("unknown user alice"));
+}
+
+/// The $PATH of the invoking user is only read for other users with -e.
+#[test]
+fn test_user_path_var() {
+    let root = Path::new(env!("CARGO_TARGET_TMPDIR")).join("rootfs-user-path-var");
+    let _ = std::fs::remove_dir_all(&root);
+    for dir in &["etc", "home/bob", "opt/invoker/bin"] {
+        std::fs::create_dir_all(root.join(dir)).unwrap();
+    }
+    std::fs::write(root.join("etc/passwd"), "bob:x:1000:1000::/home/bob:/bin/sh\n").unwrap();
+    std::fs::write(root.join("etc/group"), "bob:x:1000:\n").unwrap();
+
+    let run = |args: &[&str]| {
+        pathfix("user_path_var")
+            .args(args)
+            .arg("--root")
+            .arg(&root)
+            .env("PATH", "/opt/invoker/bin:/usr/bin:/bin")
+            .assert()
+            .success()
+    };
+    run(&["-l", "--user", "bob"]).stdout(contains("/opt/invoker/bin").not());
+    run(&["-l", "--as-user", "bob"]).stdout(contains("/opt/invoker/bin").not());
+    run(&["-el", "--user", "bob"]).stdout(contains("/opt/invoker/bin"));
+    run(&["-el", "--as-user", "bob"]).stdout(contains("/opt/invoker/bin"));
+}
+
+#[test]
+fn test_profile() {
+    let run = |args: &[&str]| {
+        pathfix("profile")
+            .arg("--config=./tests/admin.toml")
+            .args(args)
+            .assert()
+            .success()
+    };
+    run(&["--admin"]).stdout("./tests/a:./tests/b\n");
+    run(&["--sudoers", "--no-admin"]).stdout("Defaults secure_path=\"./tests/b\"\n");
+    run(&["--no-fs-check"]).stdout("./tests/b\n");
+    run(&["--target-os", "macos", "--no-fs-check"]).stdout("./tests/b:/nonexistent/pathfix\n");
+    run(&["--target-os", "macos", "--as-user", "root"]).stdout("./tests/b\n");
+}
+
+#[test]
+fn test_convert() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("convert");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("paths.txt"), "# comment\n~/bin\n/opt/corp/bin! | unix\n/usr/sbin|admin\n").unwrap();
+
+    let mut cmd = pathfix("convert");
+    cmd.arg("convert").arg(dir.join("paths.txt")).arg(dir.join("paths.toml")).assert().success();
+    let toml = std::fs::read_to_string(dir.join("paths.toml")).unwrap();
+    assert!(toml.contains("path = \"/opt/corp/bin\"\nflags = \"unix\"\nrequired = true\n"), "{}", toml);
+
+    let mut cmd = pathfix("convert");
+    let assert = cmd
+        .arg("convert")
+        .arg(dir.join("paths.toml"))
+        .args(["--to", "txt"])
+        .assert();
+    assert
+        .success()
+        .stdout("~/bin\n/opt/corp/bin!|unix\n/usr/sbin|admin,any\n");
+}
+
+#[test]
+fn test_convert_unsupported() {
+    let mut cmd = pathfix("convert_unsupported");
+    let assert = cmd
+        .args(["convert", "./tests/command.toml", "--to", "txt"])
+        .assert();
+    assert
+        .success()
+        .stdout("$TESTS/a\n$FAILING/b\n")
+        .stderr(contains("warning: [env] can not be converted"));
+}
+
+#[test]
+fn test_config_dump() {
+    let mut cmd = pathfix("config_dump");
+    let assert = cmd
+        .args(["--config=./tests/export.toml", "config", "dump"])
+        .env("TESTS", "from the environment")
+        .assert();
+    assert
+        .success()
+        .stdout(contains("# config: ./tests/export.toml\nbase = true\n"))
+        .stdout(contains("[paths]\n# config: ./tests/export.toml\n\"./tests/a\" = \"any\"\n"))
+        .stdout(contains("# config: ./tests/export.toml\nHIDDEN = \"not exported\"\n"))
+        .stdout(contains("TESTS = { export = true, value = \"./tests\" }\n"));
+}